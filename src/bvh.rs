@@ -0,0 +1,413 @@
+//! Bounding volume hierarchy, which speeds up finding the closest hit of a ray by skipping
+//! past everything whose bounding box the ray misses.
+
+use crate::{HitRecord, Ray};
+use glm::{vec3, Vec3};
+
+// number of buckets that primitive centroids get sorted into when evaluating split candidates.
+const NUM_BINS: usize = 16;
+// nodes with this many primitives or less become a leaf when splitting them does not pay off.
+const MAX_LEAF_SIZE: usize = 4;
+// relative cost of visiting a node compared to intersecting a primitive.
+const TRAVERSAL_COST: f32 = 0.125;
+// limits the depth of the tree, so traversal can use a fixed size stack.
+const MAX_DEPTH: usize = 64;
+
+/// Axis-aligned bounding box.
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    /// Corner with the smallest coordinates.
+    pub min: Vec3,
+    /// Corner with the largest coordinates.
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// Box between the corners `min` and `max`.
+    pub fn new(min: Vec3, max: Vec3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    /// An inverted box that contains nothing, and acts as the identity for union.
+    pub fn empty() -> Aabb {
+        let inf = f32::INFINITY;
+        Aabb {
+            min: vec3(inf, inf, inf),
+            max: vec3(-inf, -inf, -inf),
+        }
+    }
+
+    /// Smallest box containing both boxes.
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: glm::min2(&self.min, &other.min),
+            max: glm::max2(&self.max, &other.max),
+        }
+    }
+
+    /// Smallest box containing both this box and the point `p`.
+    pub fn grow(&self, p: &Vec3) -> Aabb {
+        Aabb {
+            min: glm::min2(&self.min, p),
+            max: glm::max2(&self.max, p),
+        }
+    }
+
+    /// Point in the middle of the box.
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// Total area of the sides of the box, or 0 for an empty box.
+    pub fn surface_area(&self) -> f32 {
+        let d = self.max - self.min;
+        if d.x < 0.0 || d.y < 0.0 || d.z < 0.0 {
+            0.0
+        } else {
+            2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+        }
+    }
+
+    /// Index of the axis along which the box is longest, with 0 for x, 1 for y and 2 for z.
+    pub fn largest_axis(&self) -> usize {
+        let d = self.max - self.min;
+        if d.x > d.y && d.x > d.z {
+            0
+        } else if d.y > d.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Slab test. The inverse ray direction is passed in, since it is the same for every box
+    /// that a ray is tested against during traversal.
+    pub fn hit(&self, origin: &Vec3, inv_dir: &Vec3, t_min: f32, t_max: f32) -> bool {
+        let mut t0 = t_min;
+        let mut t1 = t_max;
+        for axis in 0..3 {
+            let mut near = (self.min[axis] - origin[axis]) * inv_dir[axis];
+            let mut far = (self.max[axis] - origin[axis]) * inv_dir[axis];
+            if near > far {
+                std::mem::swap(&mut near, &mut far);
+            }
+            // make the far distance slightly conservative, so rounding errors can not make us
+            // miss a box that a ray just grazes.
+            far += far.abs() * 4.0 * f32::EPSILON;
+            // written so that NaNs (0 * inf for rays in a slab plane) leave the interval alone.
+            t0 = if near > t0 { near } else { t0 };
+            t1 = if far < t1 { far } else { t1 };
+            if t0 > t1 {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// node of the flattened tree. an interior node is directly followed by its first child,
+// while `offset` points to the second child. for a leaf, `offset` is the index of its first
+// primitive in `Bvh::indices` and `count` is the number of primitives.
+struct BvhNode {
+    bounds: Aabb,
+    offset: usize,
+    count: usize,
+    axis: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Bin {
+    bounds: Aabb,
+    count: usize,
+}
+
+/// Bounding volume hierarchy over a list of primitives, built using the surface area heuristic.
+/// The tree only knows about the bounding boxes of the primitives, so it can be used with
+/// anything that can be bounded. Intersecting the primitives themselves is left to the caller.
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    indices: Vec<usize>,
+}
+
+impl Bvh {
+    /// Builds the hierarchy over the primitives with the given bounding boxes. The primitives
+    /// are referred to by their index in `bounds`.
+    pub fn new(bounds: &[Aabb]) -> Bvh {
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(2 * bounds.len()),
+            indices: (0..bounds.len()).collect(),
+        };
+        if !bounds.is_empty() {
+            let centroids: Vec<Vec3> = bounds.iter().map(|b| b.centroid()).collect();
+            bvh.build_node(bounds, &centroids, 0, bounds.len(), 0);
+        }
+        bvh
+    }
+
+    fn build_node(
+        &mut self,
+        bounds: &[Aabb],
+        centroids: &[Vec3],
+        start: usize,
+        end: usize,
+        depth: usize,
+    ) -> usize {
+        let node_bounds = self.indices[start..end]
+            .iter()
+            .fold(Aabb::empty(), |acc, &i| acc.union(&bounds[i]));
+        let node_index = self.nodes.len();
+        let count = end - start;
+        self.nodes.push(BvhNode {
+            bounds: node_bounds,
+            offset: start,
+            count,
+            axis: 0,
+        });
+        if count == 1 || depth + 1 >= MAX_DEPTH {
+            return node_index;
+        }
+
+        // bin the primitives along the axis in which their centroids are spread out the most.
+        let centroid_bounds = self.indices[start..end]
+            .iter()
+            .fold(Aabb::empty(), |acc, &i| acc.grow(&centroids[i]));
+        let axis = centroid_bounds.largest_axis();
+        let axis_min = centroid_bounds.min[axis];
+        let extent = centroid_bounds.max[axis] - axis_min;
+        if extent <= 0.0 {
+            // all centroids coincide, so there is no sensible way to split these up.
+            return node_index;
+        }
+        let scale = NUM_BINS as f32 / extent;
+        let bin_index = |c: &Vec3| (((c[axis] - axis_min) * scale) as usize).min(NUM_BINS - 1);
+
+        let mut bins = [Bin {
+            bounds: Aabb::empty(),
+            count: 0,
+        }; NUM_BINS];
+        for &i in &self.indices[start..end] {
+            let bin = &mut bins[bin_index(&centroids[i])];
+            bin.bounds = bin.bounds.union(&bounds[i]);
+            bin.count += 1;
+        }
+
+        // the cost of splitting after bin k is the expected cost of intersecting both children,
+        // where the chance of a ray hitting a child is proportional to its surface area.
+        let mut costs = [0.0f32; NUM_BINS - 1];
+        let mut acc = Bin {
+            bounds: Aabb::empty(),
+            count: 0,
+        };
+        for (k, bin) in bins[..NUM_BINS - 1].iter().enumerate() {
+            acc.bounds = acc.bounds.union(&bin.bounds);
+            acc.count += bin.count;
+            costs[k] = acc.count as f32 * acc.bounds.surface_area();
+        }
+        let mut acc = Bin {
+            bounds: Aabb::empty(),
+            count: 0,
+        };
+        for k in (0..NUM_BINS - 1).rev() {
+            acc.bounds = acc.bounds.union(&bins[k + 1].bounds);
+            acc.count += bins[k + 1].count;
+            costs[k] += acc.count as f32 * acc.bounds.surface_area();
+        }
+        let mut best_split = 0;
+        for k in 1..NUM_BINS - 1 {
+            if costs[k] < costs[best_split] {
+                best_split = k;
+            }
+        }
+        let best_cost = TRAVERSAL_COST + costs[best_split] / node_bounds.surface_area();
+        if count <= MAX_LEAF_SIZE && best_cost >= count as f32 {
+            return node_index;
+        }
+
+        // partition the primitives into the two halves
+        let mut mid = start;
+        for i in start..end {
+            if bin_index(&centroids[self.indices[i]]) <= best_split {
+                self.indices.swap(i, mid);
+                mid += 1;
+            }
+        }
+        if mid == start || mid == end {
+            // binning could not separate the primitives, fall back to splitting at the median.
+            mid = start + count / 2;
+            self.indices[start..end].select_nth_unstable_by(count / 2, |&a, &b| {
+                centroids[a][axis]
+                    .partial_cmp(&centroids[b][axis])
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+        }
+
+        self.build_node(bounds, centroids, start, mid, depth + 1);
+        let second = self.build_node(bounds, centroids, mid, end, depth + 1);
+        let node = &mut self.nodes[node_index];
+        node.offset = second;
+        node.count = 0;
+        node.axis = axis;
+        node_index
+    }
+
+    /// Find the closest hit along the ray. `hit_primitive` is called with the index of each
+    /// primitive whose bounds the ray passes through, along with the current maximum distance.
+    pub fn hit<'a, F>(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        mut hit_primitive: F,
+    ) -> Option<HitRecord<'a>>
+    where
+        F: FnMut(usize, f32) -> Option<HitRecord<'a>>,
+    {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let inv_dir = vec3(
+            1.0 / ray.direction.x,
+            1.0 / ray.direction.y,
+            1.0 / ray.direction.z,
+        );
+        let dir_is_neg = [inv_dir.x < 0.0, inv_dir.y < 0.0, inv_dir.z < 0.0];
+
+        let mut closest = t_max;
+        let mut result: Option<HitRecord> = None;
+        let mut stack = [0usize; MAX_DEPTH];
+        let mut stack_len = 0;
+        let mut node_index = 0;
+        loop {
+            let node = &self.nodes[node_index];
+            if node.bounds.hit(&ray.origin, &inv_dir, t_min, closest) {
+                if node.count > 0 {
+                    for &i in &self.indices[node.offset..node.offset + node.count] {
+                        if let Some(h) = hit_primitive(i, closest) {
+                            closest = h.t;
+                            result = Some(h);
+                        }
+                    }
+                } else {
+                    // visit the child nearest to the ray origin first, so that the far child
+                    // can hopefully be culled by the closest hit found so far.
+                    if dir_is_neg[node.axis] {
+                        stack[stack_len] = node_index + 1;
+                        node_index = node.offset;
+                    } else {
+                        stack[stack_len] = node.offset;
+                        node_index += 1;
+                    }
+                    stack_len += 1;
+                    continue;
+                }
+            }
+            if stack_len == 0 {
+                break;
+            }
+            stack_len -= 1;
+            node_index = stack[stack_len];
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Diffuse;
+    use crate::scene::SceneObject;
+    use crate::shapes::Sphere;
+    use glm::normalize;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    fn sphere(position: Vec3, radius: f32) -> Sphere {
+        Sphere {
+            position,
+            radius,
+            material: Box::new(Diffuse::default()),
+        }
+    }
+
+    fn random_point(rng: &mut SmallRng, size: f32) -> Vec3 {
+        vec3(
+            rng.gen_range(-size, size),
+            rng.gen_range(-size, size),
+            rng.gen_range(-size, size),
+        )
+    }
+
+    // checks that the bvh finds the same closest hit as testing every sphere, for random rays
+    // and for rays running along the faces of the spheres' boxes. the bvh also gets the empty
+    // boxes, which hold nothing to hit.
+    fn check_against_scan(spheres: &[Sphere], empty: &[Aabb], rng: &mut SmallRng) {
+        let mut bounds: Vec<Aabb> = spheres.iter().map(|s| s.bounding_box().unwrap()).collect();
+        bounds.extend_from_slice(empty);
+        let bvh = Bvh::new(&bounds);
+        let check = |ray: &Ray| {
+            let found = bvh.hit(ray, 0.001, f32::INFINITY, |i, t_max| {
+                spheres.get(i)?.ray_hit(ray, 0.001, t_max)
+            });
+            let mut closest: Option<HitRecord> = None;
+            for s in spheres {
+                let t_max = closest.as_ref().map_or(f32::INFINITY, |h| h.t);
+                closest = s.ray_hit(ray, 0.001, t_max).or(closest);
+            }
+            assert_eq!(
+                found.map(|h| h.t),
+                closest.map(|h| h.t),
+                "hits differ for {:?}",
+                ray
+            );
+        };
+
+        for _ in 0..2000 {
+            let direction = normalize(&random_point(rng, 1.0));
+            check(&Ray::new(random_point(rng, 15.0), direction, 0.0));
+        }
+        // rays parallel to two of the axes, starting in the plane of a box face, where the
+        // slab test multiplies zero by infinity
+        for _ in 0..2000 {
+            let b = &bounds[rng.gen_range(0, spheres.len() as u32) as usize];
+            let axis = rng.gen_range(0, 3u32) as usize;
+            let mut origin = random_point(rng, 15.0);
+            let mut direction = vec3(0.0, 0.0, 0.0);
+            direction[axis] = if rng.gen() { 1.0 } else { -1.0 };
+            let face = (axis + rng.gen_range(1, 3u32) as usize) % 3;
+            origin[face] = if rng.gen() { b.min[face] } else { b.max[face] };
+            check(&Ray::new(origin, direction, 0.0));
+        }
+    }
+
+    #[test]
+    fn finds_the_same_hits_as_a_scan() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        let spheres: Vec<Sphere> = (0..300)
+            .map(|_| sphere(random_point(&mut rng, 10.0), rng.gen_range(0.05, 1.5)))
+            .collect();
+        check_against_scan(&spheres, &[], &mut rng);
+    }
+
+    #[test]
+    fn handles_coincident_centroids() {
+        let mut rng = SmallRng::seed_from_u64(2);
+        // nested spheres around a few shared centers, which can not be split by their centroids
+        let centers: Vec<Vec3> = (0..3).map(|_| random_point(&mut rng, 8.0)).collect();
+        let spheres: Vec<Sphere> = (0..120)
+            .map(|i| sphere(centers[i % 3], 0.1 + 0.02 * i as f32))
+            .collect();
+        check_against_scan(&spheres, &[], &mut rng);
+    }
+
+    #[test]
+    fn handles_the_median_split() {
+        let mut rng = SmallRng::seed_from_u64(3);
+        // centroids spread so far apart that their extent overflows, which leaves binning
+        // unable to separate them, so the nodes get split at the median instead
+        let spheres: Vec<Sphere> = (0..100)
+            .map(|_| sphere(random_point(&mut rng, 10.0), rng.gen_range(0.05, 1.5)))
+            .collect();
+        let far = |x: f32| Aabb::new(vec3(x - 1.0, -1.0, -1.0), vec3(x + 1.0, 1.0, 1.0));
+        check_against_scan(&spheres, &[far(-3e38), far(3e38)], &mut rng);
+    }
+}
@@ -0,0 +1,437 @@
+//! Cameras turn points on the image into rays into the scene. They all share a [`View`], which
+//! places them in the world, and differ in how they project the scene onto the image.
+
+use crate::ray::Ray;
+use crate::sampling::concentric_disk;
+use glm::{normalize, vec2, Vec2, Vec3};
+use std::f32::consts::PI;
+
+/// Random numbers a camera uses to generate a ray, each uniformly distributed in [0, 1).
+pub struct CameraSample {
+    /// Point on the image, where (0, 0) is the top left corner and (1, 1) the bottom right one.
+    pub film: Vec2,
+    /// Picks where on the lens the ray starts, for cameras with depth of field.
+    pub lens: Vec2,
+    /// Picks when during the shutter interval the ray travels through the scene.
+    pub time: f32,
+}
+
+/// Projection of the scene onto the image.
+pub trait Camera: Sync + Send {
+    /// Ray through the point of the image given by the sample. `image_aspect_ratio` is the ratio
+    /// of width to height of the image. Returns None for points that the projection does not
+    /// cover, like the corners of a circular fisheye image.
+    fn generate_ray(&self, sample: &CameraSample, image_aspect_ratio: f32) -> Option<Ray>;
+}
+
+/// One of the two eyes of a stereo camera.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Eye {
+    /// The left eye, offset along the camera's left.
+    Left,
+    /// The right eye, offset along the camera's right.
+    Right,
+}
+
+impl Eye {
+    // direction in which the eye is offset from the center, along the camera's right axis
+    fn sign(self) -> f32 {
+        match self {
+            Eye::Left => -1.0,
+            Eye::Right => 1.0,
+        }
+    }
+}
+
+/// Camera or cameras that a scene gets rendered with.
+pub enum Rig {
+    /// A single camera, for a single image.
+    Mono(Box<dyn Camera>),
+    /// A camera for each eye, for viewing in stereo.
+    Stereo {
+        /// Camera for the left eye.
+        left: Box<dyn Camera>,
+        /// Camera for the right eye.
+        right: Box<dyn Camera>,
+        /// How the two images are stored.
+        layout: StereoLayout,
+    },
+}
+
+/// How the images for the two eyes of a stereo rig are stored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StereoLayout {
+    /// As two separate images, left first.
+    Separate,
+    /// Packed into a single image twice as high, with the left eye on top.
+    TopBottom,
+}
+
+/// Placement of a camera in the world, and the interval during which its shutter is open.
+#[derive(Clone, Debug)]
+pub struct View {
+    eye: Vec3,
+    // orthonormal basis of the camera, pointing to the right, up and backwards respectively.
+    right: Vec3,
+    up: Vec3,
+    back: Vec3,
+    // distance between the eye and the target
+    distance: f32,
+    shutter_open: f32,
+    shutter_close: f32,
+}
+
+impl View {
+    /// Camera at `eye` looking at `target`, with `up` pointing roughly upwards in the image.
+    ///
+    /// Panics if `eye` and `target` are the same point, or if `up` is parallel to the viewing
+    /// direction.
+    pub fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> View {
+        let back = eye - target;
+        assert!(glm::length2(&back) > 0.0, "camera eye and target coincide");
+        let distance = glm::length(&back);
+        let back = back / distance;
+        let right = up.cross(&back);
+        assert!(
+            glm::length2(&right) > 0.0,
+            "camera up vector is parallel to the viewing direction"
+        );
+        let right = normalize(&right);
+        View {
+            eye,
+            right,
+            up: back.cross(&right),
+            back,
+            distance,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        }
+    }
+
+    /// Keeps the shutter open from time `open` until `close`, so anything that moves during that
+    /// interval gets motion blurred. By default the shutter only opens for an instant at time 0.
+    pub fn with_shutter(self, open: f32, close: f32) -> View {
+        View {
+            shutter_open: open,
+            shutter_close: close,
+            ..self
+        }
+    }
+
+    // turns a point and direction relative to the camera, with x to the right, y up and looking
+    // down the negative z axis, into a ray in the world at the sampled time.
+    fn ray_from(&self, origin: &Vec3, direction: &Vec3, time: f32) -> Ray {
+        let time = self.shutter_open + (self.shutter_close - self.shutter_open) * time;
+        Ray::new(
+            self.eye + self.to_world(origin),
+            self.to_world(direction),
+            time,
+        )
+    }
+
+    // same, for rays starting in the plane through the eye facing the viewing direction
+    fn ray(&self, origin: &Vec2, direction: &Vec3, time: f32) -> Ray {
+        self.ray_from(&origin.push(0.0), direction, time)
+    }
+
+    fn to_world(&self, v: &Vec3) -> Vec3 {
+        self.right * v.x + self.up * v.y + self.back * v.z
+    }
+}
+
+// point on a rectangle centered on the camera's viewing axis, with the given half height
+fn film_point(film: &Vec2, half_height: f32, aspect_ratio: f32) -> Vec2 {
+    vec2(
+        (2.0 * film.x - 1.0) * half_height * aspect_ratio,
+        (1.0 - 2.0 * film.y) * half_height,
+    )
+}
+
+/// Pinhole camera with a regular perspective projection. It can be given a lens aperture for
+/// depth of field.
+pub struct Perspective {
+    view: View,
+    // half the height of the viewport at distance 1 from the eye
+    half_height: f32,
+    aspect_ratio: Option<f32>,
+    // radius of the lens, with 0 for a pinhole
+    aperture: f32,
+    // distance along the viewing direction at which things are in perfect focus
+    focus_distance: f32,
+    // number of diaphragm blades, making the aperture a regular polygon. 0 for a round aperture.
+    blades: u32,
+    blade_rotation: f32,
+    // for stereo, how far the eye is moved to the side, and how far the image is shifted
+    // sideways at distance 1 to make the two eyes converge.
+    eye_offset: f32,
+    shift: f32,
+}
+
+impl Perspective {
+    /// `vertical_fov` is the angle in degrees between the top and bottom edges of the image.
+    pub fn new(view: View, vertical_fov: f32) -> Perspective {
+        Perspective {
+            half_height: (vertical_fov.to_radians() / 2.0).tan(),
+            aspect_ratio: None,
+            aperture: 0.0,
+            focus_distance: view.distance,
+            blades: 0,
+            blade_rotation: 0.0,
+            eye_offset: 0.0,
+            shift: 0.0,
+            view,
+        }
+    }
+
+    /// Fixes the ratio of width to height of the image. By default it follows the image being
+    /// rendered, so pixels are square; with a fixed ratio the image gets stretched to fit.
+    pub fn with_aspect_ratio(self, aspect_ratio: f32) -> Perspective {
+        Perspective {
+            aspect_ratio: Some(aspect_ratio),
+            ..self
+        }
+    }
+
+    /// Gives the camera a thin lens with the given radius, so only things at the focus
+    /// distance are sharp. A radius of 0 makes it a pinhole camera again.
+    pub fn with_aperture(self, radius: f32) -> Perspective {
+        Perspective {
+            aperture: radius,
+            ..self
+        }
+    }
+
+    /// Sets the distance along the viewing direction at which things are in focus. Defaults to
+    /// the distance between the eye and the target.
+    pub fn with_focus_distance(self, focus_distance: f32) -> Perspective {
+        Perspective {
+            focus_distance,
+            ..self
+        }
+    }
+
+    /// Makes the aperture a regular polygon with the given number of blades, rotated by
+    /// `rotation` degrees, which shows in the shape of out of focus highlights. With 0 blades the
+    /// aperture is round.
+    pub fn with_blades(self, blades: u32, rotation: f32) -> Perspective {
+        assert!(
+            blades == 0 || blades >= 3,
+            "aperture needs at least 3 blades"
+        );
+        Perspective {
+            blades,
+            blade_rotation: rotation.to_radians(),
+            ..self
+        }
+    }
+
+    /// Turns the camera into one eye of a stereo pair, with the eyes `interocular` apart. Both
+    /// eyes look in the same direction, but their images are shifted so that things at
+    /// distance `convergence` end up at the same place in both, and appear at screen depth.
+    pub fn with_stereo_eye(self, eye: Eye, interocular: f32, convergence: f32) -> Perspective {
+        let eye_offset = eye.sign() * interocular / 2.0;
+        Perspective {
+            eye_offset,
+            shift: -eye_offset / convergence,
+            ..self
+        }
+    }
+}
+
+impl Camera for Perspective {
+    fn generate_ray(&self, sample: &CameraSample, image_aspect_ratio: f32) -> Option<Ray> {
+        // the viewport is a rectangle at distance 1 in front of the eye, through which we shoot
+        // our rays.
+        let aspect_ratio = self.aspect_ratio.unwrap_or(image_aspect_ratio);
+        let p = film_point(&sample.film, self.half_height, aspect_ratio) + vec2(self.shift, 0.0);
+        let direction = p.push(-1.0);
+        let eye = vec2(self.eye_offset, 0.0);
+        if self.aperture <= 0.0 {
+            return Some(self.view.ray(&eye, &direction, sample.time));
+        }
+
+        // rays from all over the lens pass through the same point on the plane of focus
+        let focus_point = direction * self.focus_distance;
+        let lens = if self.blades == 0 {
+            concentric_disk(&sample.lens)
+        } else {
+            regular_polygon(&sample.lens, self.blades, self.blade_rotation)
+        };
+        let lens = lens * self.aperture;
+        let direction = focus_point - lens.push(0.0);
+        Some(self.view.ray(&(eye + lens), &direction, sample.time))
+    }
+}
+
+/// Camera without perspective, where all rays run parallel to the viewing direction. Objects
+/// keep the same size no matter how far away they are, as in architectural elevations.
+pub struct Orthographic {
+    view: View,
+    half_height: f32,
+    aspect_ratio: Option<f32>,
+}
+
+impl Orthographic {
+    /// `height` is the size of the area that the image covers, in world units from its top edge
+    /// to its bottom edge.
+    pub fn new(view: View, height: f32) -> Orthographic {
+        Orthographic {
+            view,
+            half_height: height / 2.0,
+            aspect_ratio: None,
+        }
+    }
+
+    /// Fixes the ratio of width to height of the image, as for [`Perspective`].
+    pub fn with_aspect_ratio(self, aspect_ratio: f32) -> Orthographic {
+        Orthographic {
+            aspect_ratio: Some(aspect_ratio),
+            ..self
+        }
+    }
+}
+
+impl Camera for Orthographic {
+    fn generate_ray(&self, sample: &CameraSample, image_aspect_ratio: f32) -> Option<Ray> {
+        let aspect_ratio = self.aspect_ratio.unwrap_or(image_aspect_ratio);
+        let p = film_point(&sample.film, self.half_height, aspect_ratio);
+        Some(self.view.ray(&p, &Vec3::new(0.0, 0.0, -1.0), sample.time))
+    }
+}
+
+/// How a fisheye lens maps the angle between a ray and the viewing direction onto the distance
+/// from the center of the image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FisheyeMapping {
+    /// Distance proportional to the angle, keeping angles between directions intact.
+    Equidistant,
+    /// Distance proportional to the sine of half the angle, keeping areas intact.
+    Equisolid,
+}
+
+/// Fisheye camera, producing a circular image that fits within the shorter side of the frame.
+pub struct Fisheye {
+    view: View,
+    mapping: FisheyeMapping,
+    // half the angle covered by the image circle, in radians
+    half_angle: f32,
+}
+
+impl Fisheye {
+    /// `field_of_view` is the angle in degrees covered by the diameter of the image circle, up
+    /// to 360 degrees.
+    pub fn new(view: View, mapping: FisheyeMapping, field_of_view: f32) -> Fisheye {
+        Fisheye {
+            view,
+            mapping,
+            half_angle: field_of_view.to_radians().min(2.0 * PI) / 2.0,
+        }
+    }
+}
+
+impl Camera for Fisheye {
+    fn generate_ray(&self, sample: &CameraSample, image_aspect_ratio: f32) -> Option<Ray> {
+        // scale the image so the unit circle touches the shorter sides
+        let p = film_point(&sample.film, 1.0, image_aspect_ratio) / image_aspect_ratio.min(1.0);
+        let r = glm::length(&p);
+        if r > 1.0 {
+            return None;
+        }
+        let theta = match self.mapping {
+            FisheyeMapping::Equidistant => r * self.half_angle,
+            FisheyeMapping::Equisolid => 2.0 * (r * (self.half_angle / 2.0).sin()).asin(),
+        };
+        let phi = p.y.atan2(p.x);
+        let direction = Vec3::new(
+            theta.sin() * phi.cos(),
+            theta.sin() * phi.sin(),
+            -theta.cos(),
+        );
+        Some(self.view.ray(&vec2(0.0, 0.0), &direction, sample.time))
+    }
+}
+
+/// 360 degree panoramic camera, mapping longitude and latitude linearly onto the horizontal and
+/// vertical axes of the image. The viewing direction ends up in the center of the image, and the
+/// image should be twice as wide as it is high.
+pub struct Equirectangular {
+    view: View,
+}
+
+impl Equirectangular {
+    /// Panorama around the eye of `view`, centered on its viewing direction.
+    pub fn new(view: View) -> Equirectangular {
+        Equirectangular { view }
+    }
+}
+
+impl Camera for Equirectangular {
+    fn generate_ray(&self, sample: &CameraSample, _image_aspect_ratio: f32) -> Option<Ray> {
+        let (longitude, latitude) = longitude_latitude(&sample.film);
+        let direction = direction_at(longitude, latitude);
+        Some(self.view.ray(&vec2(0.0, 0.0), &direction, sample.time))
+    }
+}
+
+/// Omnidirectional stereo camera: an [`Equirectangular`] panorama for one eye, where the eyes
+/// turn along with the direction being looked at. Every ray starts at the eye position of
+/// someone turned towards it, on a circle with the interocular distance as its diameter.
+pub struct Ods {
+    view: View,
+    // radius of the circle the eye moves on, negative for the left eye
+    eye_offset: f32,
+}
+
+impl Ods {
+    /// Panorama for the given eye, with `interocular` the distance between the eyes.
+    pub fn new(view: View, eye: Eye, interocular: f32) -> Ods {
+        Ods {
+            view,
+            eye_offset: eye.sign() * interocular / 2.0,
+        }
+    }
+}
+
+impl Camera for Ods {
+    fn generate_ray(&self, sample: &CameraSample, _image_aspect_ratio: f32) -> Option<Ray> {
+        let (longitude, latitude) = longitude_latitude(&sample.film);
+        let direction = direction_at(longitude, latitude);
+        // the eye sits to the side of the center, perpendicular to the horizontal direction
+        // being looked in.
+        let eye = Vec3::new(longitude.cos(), 0.0, longitude.sin()) * self.eye_offset;
+        Some(self.view.ray_from(&eye, &direction, sample.time))
+    }
+}
+
+// longitude and latitude in radians of a point on an equirectangular image, with the center of
+// the image at longitude and latitude 0.
+fn longitude_latitude(film: &Vec2) -> (f32, f32) {
+    ((2.0 * film.x - 1.0) * PI, (0.5 - film.y) * PI)
+}
+
+// direction relative to the camera, at the given longitude and latitude from the viewing
+// direction.
+fn direction_at(longitude: f32, latitude: f32) -> Vec3 {
+    Vec3::new(
+        latitude.cos() * longitude.sin(),
+        latitude.sin(),
+        -latitude.cos() * longitude.cos(),
+    )
+}
+
+// maps a point in the unit square to a uniformly distributed point in a regular polygon with
+// its corners on the unit circle. the polygon is made up of triangles between the center and
+// each pair of neighbouring corners, which all have the same area.
+fn regular_polygon(u: &Vec2, corners: u32, rotation: f32) -> Vec2 {
+    // use the first coordinate to pick a triangle, and what is left of it as a fresh sample
+    let x = u.x * corners as f32;
+    let i = (x as u32).min(corners - 1);
+    let ux = x - i as f32;
+    let angle = 2.0 * PI / corners as f32;
+    let phi0 = rotation + angle * i as f32;
+    let c0 = vec2(phi0.cos(), phi0.sin());
+    let c1 = vec2((phi0 + angle).cos(), (phi0 + angle).sin());
+    // uniform point in the triangle with the center as its third corner
+    let s = ux.sqrt();
+    c0 * (s * (1.0 - u.y)) + c1 * (s * u.y)
+}
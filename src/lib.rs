@@ -0,0 +1,75 @@
+//! A very simple path tracer.
+//!
+//! Scenes are built from [`SceneObject`]s, like [`Sphere`]s, [`Plane`]s and triangle meshes,
+//! each with a [`Material`] whose colors may vary over the surface through a [`Texture`]. They
+//! can be constructed in code, or loaded from a json scene file with
+//! [`scene_file::load_scene`]. A [`Camera`], like a [`Perspective`] one, then looks at the
+//! scene, and [`render()`] renders what it sees into a [`Framebuffer`]:
+//!
+//! ```no_run
+//! use raytracer::glm::vec3;
+//! use raytracer::{render, Diffuse, Perspective, RenderSettings, Scene, SceneObject, Sphere, View};
+//!
+//! let objects: Vec<Box<dyn SceneObject>> = vec![Box::new(Sphere {
+//!     position: vec3(0.0, 0.0, -5.0),
+//!     radius: 1.0,
+//!     material: Box::new(Diffuse::default()),
+//! })];
+//! let scene = Scene::new(objects);
+//! let view = View::look_at(
+//!     vec3(0.0, 0.0, 0.0),
+//!     vec3(0.0, 0.0, -1.0),
+//!     vec3(0.0, 1.0, 0.0),
+//! );
+//! let camera = Perspective::new(view, 40.0);
+//! let settings = RenderSettings::default();
+//! let image = render(&camera, &scene, &settings).to_image();
+//! image.save(&settings.output).unwrap();
+//! ```
+
+#![warn(missing_docs)]
+
+extern crate image;
+pub extern crate nalgebra_glm as glm;
+extern crate rand;
+extern crate rayon;
+
+pub mod bvh;
+pub mod camera;
+pub mod light;
+pub mod material;
+pub mod mesh;
+pub mod microfacet;
+pub mod motion;
+pub mod obj;
+pub mod ray;
+pub mod render;
+pub mod sampler;
+pub mod sampling;
+pub mod scene;
+pub mod scene_file;
+pub mod shapes;
+pub mod texture;
+
+pub use camera::{
+    Camera, CameraSample, Equirectangular, Eye, Fisheye, FisheyeMapping, Ods, Orthographic,
+    Perspective, Rig, StereoLayout, View,
+};
+pub use material::{
+    BsdfSample, Conductor, Dielectric, Diffuse, Emissive, Material, Metal, NormalMap, NormalMapped,
+    Principled,
+};
+pub use mesh::{Triangle, TriangleMesh};
+pub use motion::{Animated, Keyframe, Motion, Transform};
+pub use ray::Ray;
+pub use render::{
+    render, render_image, render_rig, trace_ray, AdaptiveSampling, Framebuffer, RenderSettings,
+};
+pub use sampler::{
+    HaltonSampler, IndependentSampler, Sampler, SamplerKind, SobolSampler, StratifiedSampler,
+};
+pub use scene::{Background, HitRecord, Scene, SceneObject};
+pub use shapes::{Plane, Sphere};
+pub use texture::{
+    Checker, ColorRamp, ImageTexture, NoisePattern, Perlin, Procedural, Texture, WrapMode,
+};
@@ -0,0 +1,116 @@
+//! Direct light sampling, also known as next event estimation. Instead of hoping that a randomly
+//! scattered ray happens to hit a light, we pick a point on one of the lights and check whether
+//! it is visible. Both strategies are combined using multiple importance sampling, so each one
+//! gets the most weight where it works best.
+
+use crate::sampler::Sampler;
+use crate::{HitRecord, Ray, Scene};
+use glm::{dot, vec3, Vec2, Vec3};
+
+/// Point sampled on the surface of an object, as seen from some reference point.
+pub struct SurfaceSample {
+    /// The sampled point.
+    pub point: Vec3,
+    /// Outward facing surface normal at the sampled point.
+    pub normal: Vec3,
+    /// Surface parameterization at the sampled point, for looking up textures.
+    pub uv: Vec2,
+    /// Probability density of sampling this point, with respect to solid angle as seen from the
+    /// reference point.
+    pub pdf: f32,
+}
+
+/// Weight for a sample taken with a strategy that had probability density `pdf`, when combined
+/// with another strategy that would have produced it with density `other_pdf`.
+pub fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let a = pdf * pdf;
+    let b = other_pdf * other_pdf;
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
+/// Probability density, in solid angle as seen from `origin`, of the light sampling strategy
+/// picking the point where the ray from `origin` at `time` hit.
+pub fn light_pdf(scene: &Scene, origin: &Vec3, hit: &HitRecord, time: f32) -> f32 {
+    if scene.lights.is_empty() || !hit.material.is_emissive() {
+        return 0.0;
+    }
+    hit.object.light_pdf(origin, hit, time) / scene.lights.len() as f32
+}
+
+/// Estimate of the light arriving directly from the lights in the scene at `hit`, and scattered
+/// towards where `ray` came from.
+pub fn sample_direct_light(
+    ray: &Ray,
+    hit: &HitRecord,
+    scene: &Scene,
+    sampler: &mut dyn Sampler,
+) -> Vec3 {
+    let black = vec3(0.0, 0.0, 0.0);
+    if scene.lights.is_empty() {
+        return black;
+    }
+
+    // pick one of the lights uniformly, and a point on it
+    let count = scene.lights.len();
+    let index = ((sampler.get_1d() * count as f32) as usize).min(count - 1);
+    let light = &scene.objects[scene.lights[index]];
+    let sample = match light.sample(&hit.point, &sampler.get_2d(), ray.time) {
+        Some(s) if s.pdf > 0.0 => s,
+        _ => return black,
+    };
+    let light_pdf = sample.pdf / scene.lights.len() as f32;
+
+    let to_light = sample.point - hit.point;
+    let distance = glm::length(&to_light);
+    let direction = to_light / distance;
+    let f = hit.material.eval(ray, hit, &direction);
+    if f == black {
+        return black;
+    }
+
+    // stop just short of the light, so the light itself does not count as an occluder
+    let shadow_ray = Ray::new(hit.point, direction, ray.time);
+    if scene.hit(&shadow_ray, 0.001, distance * 0.999).is_some() {
+        return black;
+    }
+
+    let light_hit = HitRecord::new(
+        &shadow_ray,
+        distance,
+        &sample.normal,
+        sample.uv,
+        light.as_ref(),
+    );
+    let emitted = light_hit.material.emitted(&shadow_ray, &light_hit);
+    let weight = power_heuristic(light_pdf, hit.material.pdf(ray, hit, &direction));
+    f.component_mul(&emitted) * (weight / light_pdf)
+}
+
+/// Builds two vectors that together with unit vector n form an orthonormal basis
+/// (Duff et al., 2017).
+pub fn orthonormal_basis(n: &Vec3) -> (Vec3, Vec3) {
+    let sign = 1.0f32.copysign(n.z);
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+    (
+        vec3(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+        vec3(b, sign + n.y * n.y * a, -n.y),
+    )
+}
+
+/// Converts a density with respect to surface area into one with respect to solid angle, as
+/// seen from `origin`, for a surface point with the given normal.
+pub fn area_to_solid_angle(pdf_area: f32, origin: &Vec3, point: &Vec3, normal: &Vec3) -> f32 {
+    let to_point = point - origin;
+    let distance2 = glm::length2(&to_point);
+    let cos = dot(normal, &to_point).abs() / distance2.sqrt();
+    if cos > 0.0 {
+        pdf_area * distance2 / cos
+    } else {
+        0.0
+    }
+}
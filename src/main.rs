@@ -1,59 +1,244 @@
+extern crate clap;
 extern crate image;
-extern crate nalgebra_glm as glm;
 extern crate rand;
 extern crate rayon;
-
-use glm::{dot, normalize, vec3, Mat4, Vec3};
-use image::{Rgb, RgbImage};
-use rand::Rng;
-use rayon::prelude::*;
-use std::path::Path;
+extern crate raytracer;
+
+use clap::{Parser, ValueEnum};
+use image::{DynamicImage, ImageOutputFormat, RgbImage};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use raytracer::glm::vec3;
+use raytracer::scene_file;
+use raytracer::{
+    render_rig, Camera, Diffuse, Framebuffer, Material, Metal, Perspective, Plane, RenderSettings,
+    Rig, SamplerKind, Scene, SceneObject, Sphere, View,
+};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 
+/// A very simple raytracer.
+///
+/// Renders the given scene file, or a built-in scene of randomly placed spheres.
+/// Options given here override the render settings from the scene file.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// Json scene file to render
+    scene: Option<PathBuf>,
+
+    /// Image width in pixels
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    width: Option<u32>,
+
+    /// Image height in pixels
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    height: Option<u32>,
+
+    /// Number of samples per pixel
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    spp: Option<u32>,
+
+    /// Maximum number of bounces per path
+    #[arg(long)]
+    max_depth: Option<u32>,
+
+    /// Relative error at which pixels stop taking samples. Turns on adaptive sampling, with
+    /// --spp as the most samples a pixel takes
+    #[arg(long, value_parser = parse_threshold)]
+    adaptive_threshold: Option<f32>,
+
+    /// Fewest samples a pixel takes with adaptive sampling, which this turns on [default: 16]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    min_spp: Option<u32>,
+
+    /// Sampler to take the samples with [default: sobol]
+    #[arg(long, value_enum)]
+    sampler: Option<SamplerArg>,
+
+    /// Seed for generating the built-in scene and for the random numbers of the render
+    /// [default: 0]
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Number of threads to render with [default: number of cpu cores]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    threads: Option<u32>,
+
+    /// Output image path
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Where to write a heatmap of the number of samples taken for each pixel
+    #[arg(long)]
+    heatmap: Option<PathBuf>,
+
+    /// Output image format [default: derived from the output file extension]
+    #[arg(long, value_enum)]
+    format: Option<OutputFormat>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SamplerArg {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    Png,
+    Jpeg,
+    Bmp,
+    Ppm,
+}
+
 fn main() {
-    let width = 2048;
-    let height = 1024;
-    let mut img: RgbImage = RgbImage::new(width, height);
+    let args = Args::parse();
 
-    // position camera to the back and to the left from the origin.
-    let cam_transform = glm::translate(&glm::identity(), &vec3(0.0, 5.0, 20.0));
-    // point to the right, and then downwards
-    //let cam_transform = glm::rotate_y(&cam_transform, -0.5);
-    let cam_transform = glm::rotate_x(&cam_transform, -0.3);
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads as usize)
+            .build_global()
+            .expect("failed to set up the thread pool");
+    }
 
-    let camera = Camera {
-        transform: cam_transform,
-        field_of_view: 30.0,
+    // render the scene file given on the command line, or the default scene
+    let (scene, rig, mut settings) = match &args.scene {
+        Some(path) => match scene_file::load_scene(path) {
+            Ok(loaded) => (loaded.scene, loaded.rig, loaded.settings),
+            Err(e) => {
+                eprintln!("error loading scene: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => {
+            let settings = RenderSettings::default();
+            let scene = create_scene(args.seed.unwrap_or(settings.seed));
+            (scene, Rig::Mono(create_camera()), settings)
+        }
     };
-
-    let scene = create_scene();
+    settings.width = args.width.unwrap_or(settings.width);
+    settings.height = args.height.unwrap_or(settings.height);
+    settings.samples = args.spp.unwrap_or(settings.samples);
+    settings.max_depth = args.max_depth.unwrap_or(settings.max_depth);
+    settings.seed = args.seed.unwrap_or(settings.seed);
+    settings.sampler = match args.sampler {
+        Some(SamplerArg::Independent) => SamplerKind::Independent,
+        Some(SamplerArg::Stratified) => SamplerKind::Stratified,
+        Some(SamplerArg::Halton) => SamplerKind::Halton,
+        Some(SamplerArg::Sobol) => SamplerKind::Sobol,
+        None => settings.sampler,
+    };
+    if args.adaptive_threshold.is_some() || args.min_spp.is_some() {
+        let mut adaptive = settings.adaptive.unwrap_or_default();
+        adaptive.threshold = args.adaptive_threshold.unwrap_or(adaptive.threshold);
+        adaptive.min_samples = args.min_spp.unwrap_or(adaptive.min_samples);
+        settings.adaptive = Some(adaptive);
+    }
+    settings.output = args.output.unwrap_or(settings.output);
+    settings.heatmap = args.heatmap.or(settings.heatmap);
 
     let now = Instant::now();
-    render_image(&camera, &scene, &mut img);
+    let framebuffers = render_rig(&rig, &scene, &settings);
     let duration = now.elapsed().as_secs();
     println!("rendering image took {:.2}s", duration);
 
-    let path = &Path::new("target/out.png");
-    let _ = img.save(path);
+    let images = framebuffers.iter().map(Framebuffer::to_image);
+    save_images(images, &settings.output, args.format);
+    if let Some(heatmap) = &settings.heatmap {
+        let images = framebuffers.iter().map(|f| f.heatmap(settings.samples));
+        save_images(images, heatmap, None);
+    }
+}
+
+// separate stereo images get the eye they are for added to their file name
+// parses the relative error threshold of adaptive sampling, which must be a positive number
+fn parse_threshold(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(threshold) if threshold > 0.0 && threshold.is_finite() => Ok(threshold),
+        Ok(_) => Err("must be a positive number".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn save_images(
+    images: impl ExactSizeIterator<Item = RgbImage>,
+    path: &Path,
+    format: Option<OutputFormat>,
+) {
+    let paths = match images.len() {
+        1 => vec![path.to_path_buf()],
+        _ => vec![add_suffix(path, "_left"), add_suffix(path, "_right")],
+    };
+    for (img, path) in images.zip(&paths) {
+        if let Err(e) = save_image(img, path, format) {
+            eprintln!("error saving {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// inserts the suffix into the file name, before the extension
+fn add_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+fn save_image(
+    img: RgbImage,
+    path: &Path,
+    format: Option<OutputFormat>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let format = match format {
+        Some(OutputFormat::Png) => ImageOutputFormat::PNG,
+        Some(OutputFormat::Jpeg) => ImageOutputFormat::JPEG(95),
+        Some(OutputFormat::Bmp) => ImageOutputFormat::BMP,
+        Some(OutputFormat::Ppm) => ImageOutputFormat::PNM(image::pnm::PNMSubtype::Pixmap(
+            image::pnm::SampleEncoding::Binary,
+        )),
+        // let the image crate figure it out from the extension
+        None => return Ok(img.save(path)?),
+    };
+    let mut file = BufWriter::new(File::create(path)?);
+    DynamicImage::ImageRgb8(img).write_to(&mut file, format)?;
+    Ok(())
+}
+
+fn create_camera() -> Box<dyn Camera> {
+    // position camera to the back of the origin, looking slightly downwards.
+    let view = View::look_at(
+        vec3(0.0, 5.0, 20.0),
+        vec3(0.0, -1.2, 0.0),
+        vec3(0.0, 1.0, 0.0),
+    );
+    Box::new(Perspective::new(view, 60.0))
 }
 
-fn create_scene() -> Vec<Box<SceneObject>> {
+fn create_scene(seed: u64) -> Scene {
     let mut scene: Vec<Box<dyn SceneObject>> = Vec::new();
 
-    // add 'ground' (we could implement a plane shape, but I'm lazy so right now just use  large sphere)
-    let ground = Box::new(Sphere {
-        //position: vec3(rng.gen_range(0.0,10.0), 1.0, rng.gen_range(0.0,10.0)),
-        position: vec3(0.0, -10000.0, 0.0),
-        radius: 10000.0,
+    // add 'ground'
+    let ground = Box::new(Plane {
+        point: vec3(0.0, 0.0, 0.0),
+        normal: vec3(0.0, 1.0, 0.0),
         material: Box::new(Diffuse {
-            albedo: vec3(1.0, 1.0, 1.0),
+            albedo: Box::new(vec3(1.0, 1.0, 1.0)),
         }),
     });
     scene.push(ground);
 
     let num_spheres = 80;
     let extends = 20.0;
-    let mut rng = rand::thread_rng();
+    let mut rng = StdRng::seed_from_u64(seed);
     for _ in 0..num_spheres {
         let rad = 3.0 * rng.gen_range(0.25, 1.0) * rng.gen_range(0.25, 1.0);
         let pos = vec3(
@@ -63,14 +248,14 @@ fn create_scene() -> Vec<Box<SceneObject>> {
         );
 
         let rnd_mat: f32 = rng.gen();
-        let mat: Box<Material> = if rnd_mat < 0.5 {
+        let mat: Box<dyn Material> = if rnd_mat < 0.5 {
             Box::new(Diffuse {
-                albedo: vec3(rng.gen(), rng.gen(), rng.gen()),
+                albedo: Box::new(vec3(rng.gen(), rng.gen(), rng.gen())),
             })
         } else {
             Box::new(Metal {
-                albedo: vec3(rng.gen(), rng.gen(), rng.gen()),
-                scattering: 0.0,
+                albedo: Box::new(vec3(rng.gen(), rng.gen(), rng.gen())),
+                scattering: Box::new(0.0),
             })
         };
 
@@ -81,278 +266,5 @@ fn create_scene() -> Vec<Box<SceneObject>> {
         });
         scene.push(sphere);
     }
-    scene
-}
-
-fn render_image(camera: &Camera, scene: &Vec<Box<SceneObject>>, image: &mut RgbImage) {
-    let width = image.width();
-    let height = image.height();
-    let aspect_ratio = (width as f32) / (height as f32);
-    let num_samples = 64;
-    let pixel_indices: Vec<(u32, u32, &mut Rgb<u8>)> = image.enumerate_pixels_mut().collect();
-
-    // for each pixel, shoot rays to determine color.
-    // use rayon's parallel iterator to divide work over all cpu cores.
-    let pixels: Vec<(u32, u32, Rgb<u8>)> = pixel_indices
-        .par_iter()
-        .map(|(x, y, _)| {
-            // do a number of ray samples
-            let mut rng = rand::thread_rng();
-            let mut total_color = vec3(0.0, 0.0, 0.0);
-            for _s in 0..num_samples {
-                let u: f32 = (*x as f32 + rng.gen::<f32>()) / width as f32;
-                let v: f32 = (*y as f32 + rng.gen::<f32>()) / height as f32;
-                let ray = camera.screen_to_ray(u, v, aspect_ratio);
-                let depth = 0;
-                let color = trace_ray(&ray, &scene, depth);
-                total_color += color;
-            }
-            // determine final result pixel color
-            let total_color = total_color / num_samples as f32;
-            let final_color = encode_gamma(&total_color, 2.2);
-            let c: Rgb<u8> = vec3_to_rgb(&final_color);
-            (*x, *y, c)
-        })
-        .collect();
-
-    // write pixel data to image
-    for (x, y, c) in pixels {
-        image.put_pixel(x, y, c);
-    }
-}
-
-fn scene_hit<'a>(
-    ray: &Ray,
-    scene: &'a Vec<Box<SceneObject>>,
-    min_t: f32,
-    max_t: f32,
-) -> Option<HitRecord<'a>> {
-    // determine closest hit
-    let mut closest: f32 = std::f32::MAX;
-    let mut result: Option<HitRecord> = None;
-    for obj in scene.iter() {
-        match obj.ray_hit(&ray, min_t, max_t) {
-            None => {}
-            Some(h) => {
-                if h.t < closest {
-                    closest = h.t;
-                    result = Some(h)
-                }
-            }
-        }
-    }
-    result
-}
-
-fn trace_ray(ray: &Ray, scene: &Vec<Box<SceneObject>>, depth: u32) -> Vec3 {
-    let hit = scene_hit(ray, scene, 0.001, std::f32::MAX);
-    // return color for closest hit, or background
-    match hit {
-        // we hit something, so do a bounce in a random direction
-        Some(h) => {
-            if depth < 64 {
-                let (atten, scattered_ray) = h.material.scatter(&ray, &h);
-                match scattered_ray {
-                    Some(r) => {
-                        let col = trace_ray(&r, &scene, depth + 1);
-                        vec3(atten.x * col.x, atten.y * col.y, atten.z * col.z)
-                    }
-                    None => vec3(0.0, 0.0, 0.0),
-                }
-            } else {
-                vec3(0.0, 0.0, 0.0)
-            }
-        }
-        // we did not hit anything, so return background color
-        None => background_color_gradient(&ray),
-    }
-}
-
-trait SceneObject: Sync + Send {
-    fn ray_hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
-    fn get_material<'a>(&'a self) -> &'a Box<dyn Material>;
-}
-
-struct Sphere {
-    position: Vec3,
-    radius: f32,
-    material: Box<dyn Material>,
-}
-
-impl SceneObject for Sphere {
-    fn ray_hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        // define sphere with center C and radius R where all point P on sphere satisfy:
-        //            ||P-C||^2 = R^2
-        // or: dot((P-C),(P-C)) = R^2
-        //
-        // determine intersection with Ray by replacing P with A+tB and solving for t:
-        // dot((A+tB-C), (A+tB-C)) = R^2
-        // t^2 * dot(B,B) + 2t * dot(B,A-C) + dot(A-C,A-C) - r^2 = 0
-        //
-        // which we can also write as:
-        // a(t^2) + bt + c = 0
-        // where
-        // a = dot(B,B)
-        // b = 2*dot(B,A-C)
-        // c = dot(A-C,A-C) - r^2
-        //
-        // so we can use the standard abc quadratic formula
-        //     -b +- srt(b^2 - 4ac)
-        // t = --------------------
-        //             2a
-        let ac = ray.origin - self.position;
-        let a = dot(&ray.direction, &ray.direction);
-        let b = 2.0 * dot(&ac, &ray.direction);
-        let c = dot(&ac, &ac) - self.radius * self.radius;
-        let discr = b * b - 4.0 * a * c;
-        if discr < 0.0 {
-            None
-        } else {
-            let t = (-b - discr.sqrt()) / (2.0 * a);
-            if t > t_max || t < t_min {
-                None
-            } else {
-                let p = ray.point_at(t);
-                let n = normalize(&(p - self.position));
-                Some(HitRecord {
-                    t: t,
-                    point: p,
-                    normal: n,
-                    material: &self.material,
-                })
-            }
-        }
-    }
-
-    fn get_material<'a>(&'a self) -> &'a Box<Material> {
-        &self.material
-    }
-}
-
-struct Camera {
-    transform: Mat4,
-    field_of_view: f32,
-}
-
-impl Camera {
-    fn screen_to_ray(&self, u: f32, v: f32, aspect_ratio: f32) -> Ray {
-        // define rectangle through which we will shoot our rays, aka our viewport.
-        let angle_rad = self.field_of_view.to_radians();
-
-        let rect_dist = angle_rad.cos();
-        let rect_extends_y = angle_rad.sin();
-        let rect_extends_x = rect_extends_y * aspect_ratio;
-        let horizontal = vec3(rect_extends_x, 0.0, 0.0) * 2.0;
-        let vertical = vec3(0.0, rect_extends_y, 0.0) * 2.0;
-
-        let lower_left = vec3(-rect_extends_x, -rect_extends_y, -rect_dist);
-
-        let direction = lower_left + horizontal * u + vertical * (1.0 - v);
-
-        let origin: Vec3 = (self.transform * glm::vec4(0.0, 0.0, 0.0, 1.0)).xyz();
-        let direction: Vec3 = (self.transform * glm::vec3_to_vec4(&direction)).xyz();
-
-        Ray { origin, direction }
-    }
-}
-
-struct Ray {
-    origin: Vec3,
-    direction: Vec3,
-}
-
-impl Ray {
-    fn new(origin: Vec3, direction: Vec3) -> Ray {
-        Ray { origin, direction }
-    }
-
-    fn point_at(&self, t: f32) -> Vec3 {
-        self.origin + self.direction * t
-    }
-}
-
-fn background_color_gradient(ray: &Ray) -> Vec3 {
-    let unit_dir: Vec3 = glm::normalize(&ray.direction);
-    let ground_color: Vec3 = Vec3::new(1.0, 1.0, 1.0);
-    let sky_color: Vec3 = Vec3::new(0.2, 0.4, 0.8);
-    let t = 0.5 * (unit_dir.y + 1.0);
-    glm::lerp(&ground_color, &sky_color, t)
-}
-
-fn vec3_to_rgb(v: &Vec3) -> Rgb<u8> {
-    let a = v * 255.99;
-    let b = glm::clamp(&a, 0.0, 255.0);
-    Rgb([b.x as u8, b.y as u8, b.z as u8])
-}
-
-fn rand_unit_sphere() -> Vec3 {
-    let mut rng = rand::thread_rng();
-    let mut p: Vec3 = vec3(1.0, 1.0, 1.0);
-    while glm::length2(&p) > 1.0 {
-        p = 2.0 * Vec3::new(rng.gen(), rng.gen(), rng.gen()) - vec3(1.0, 1.0, 1.0);
-    }
-    p
-}
-
-fn encode_gamma(color: &Vec3, gamma: f32) -> Vec3 {
-    let inv = 1.0 / gamma;
-    let exp = vec3(inv, inv, inv);
-    glm::pow(color, &exp)
-}
-
-fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
-    v - 2.0 * dot(v, n) * n
-}
-
-struct HitRecord<'a> {
-    t: f32,
-    point: Vec3,
-    normal: Vec3,
-    material: &'a Box<Material>,
-}
-
-trait Material: Send + Sync {
-    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> (Vec3, Option<Ray>);
-}
-
-struct Diffuse {
-    albedo: Vec3,
-}
-
-impl Material for Diffuse {
-    fn scatter(&self, _ray: &Ray, hit: &HitRecord) -> (Vec3, Option<Ray>) {
-        let attenuation = self.albedo;
-        let ray = Ray {
-            origin: hit.point,
-            direction: hit.normal + rand_unit_sphere(),
-        };
-        (attenuation, Some(ray))
-    }
-}
-
-impl Default for Diffuse {
-    fn default() -> Self {
-        Diffuse {
-            albedo: vec3(1.0, 1.0, 1.0),
-        }
-    }
-}
-
-struct Metal {
-    albedo: Vec3,
-    scattering: f32,
-}
-
-impl Material for Metal {
-    fn scatter(&self, _ray: &Ray, hit: &HitRecord) -> (Vec3, Option<Ray>) {
-        let reflected = reflect(&_ray.direction, &hit.normal);
-        let scattered_ray = Ray::new(hit.point, reflected + self.scattering * rand_unit_sphere());
-        let attenuation = self.albedo;
-
-        if dot(&scattered_ray.direction, &hit.normal) > 0.0 {
-            (attenuation, Some(scattered_ray))
-        } else {
-            (attenuation, None)
-        }
-    }
+    Scene::new(scene)
 }
@@ -0,0 +1,992 @@
+//! Materials, which describe how surfaces scatter the light that reaches them.
+
+use crate::light::orthonormal_basis;
+use crate::microfacet::{fresnel_conductor, Ggx};
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::sampling::{cosine_hemisphere, cosine_hemisphere_pdf, uniform_ball};
+use crate::scene::HitRecord;
+use crate::texture::Texture;
+use glm::{dot, normalize, vec2, vec3, Vec2, Vec3};
+use std::f32::consts::PI;
+
+/// Direction picked by [`Material::sample`] for the ray to continue in.
+pub struct BsdfSample {
+    /// Unit vector pointing away from the surface.
+    pub direction: Vec3,
+    /// The bsdf times the cosine of the angle with the normal, divided by the pdf. This is the
+    /// fraction of the light arriving from the direction that gets scattered back along the ray.
+    pub weight: Vec3,
+    /// Probability density of picking the direction, with respect to solid angle. Delta samples
+    /// have no density, and leave this at zero.
+    pub pdf: f32,
+    /// Whether the direction came from a delta lobe, like those of mirrors and smooth glass,
+    /// which scatter light into a single direction only. `eval` and `pdf` leave these out.
+    pub delta: bool,
+}
+
+/// Describes how light interacts with a surface. Directions are unit vectors pointing away from
+/// the surface, with the light arriving from them getting scattered back along the ray that hit
+/// the surface.
+pub trait Material: Send + Sync {
+    /// Picks a direction for the ray to continue in after hitting the surface, about in
+    /// proportion to how much light it scatters, with random numbers taken from the sampler.
+    /// Returns None if the ray got absorbed.
+    fn sample(&self, ray: &Ray, hit: &HitRecord, sampler: &mut dyn Sampler) -> Option<BsdfSample>;
+
+    /// The bsdf times the cosine of the angle with the normal, for light arriving from
+    /// `direction`. Delta lobes are left out, as a given direction never lines up with them.
+    fn eval(&self, _ray: &Ray, _hit: &HitRecord, _direction: &Vec3) -> Vec3 {
+        vec3(0.0, 0.0, 0.0)
+    }
+
+    /// Probability density of `sample` picking `direction`, leaving out delta lobes.
+    fn pdf(&self, _ray: &Ray, _hit: &HitRecord, _direction: &Vec3) -> f32 {
+        0.0
+    }
+
+    /// Light given off by the surface itself, towards where the ray came from.
+    fn emitted(&self, _ray: &Ray, _hit: &HitRecord) -> Vec3 {
+        vec3(0.0, 0.0, 0.0)
+    }
+
+    /// Whether the material gives off light, which makes objects with it get sampled as lights.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+}
+
+/// Matte surface that scatters light equally in all directions.
+pub struct Diffuse {
+    /// Fraction of the light that gets scattered, for red, green and blue.
+    pub albedo: Box<dyn Texture>,
+}
+
+impl Material for Diffuse {
+    fn sample(&self, ray: &Ray, hit: &HitRecord, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let direction = Frame::new(&hit.normal).to_world(&cosine_hemisphere(&sampler.get_2d()));
+        Some(BsdfSample {
+            direction,
+            weight: self.albedo.value(&hit.uv, &hit.point, &hit.normal),
+            pdf: self.pdf(ray, hit, &direction),
+            delta: false,
+        })
+    }
+
+    fn eval(&self, ray: &Ray, hit: &HitRecord, direction: &Vec3) -> Vec3 {
+        self.albedo.value(&hit.uv, &hit.point, &hit.normal) * self.pdf(ray, hit, direction)
+    }
+
+    fn pdf(&self, _ray: &Ray, hit: &HitRecord, direction: &Vec3) -> f32 {
+        cosine_hemisphere_pdf(dot(&hit.normal, direction))
+    }
+}
+
+impl Default for Diffuse {
+    fn default() -> Self {
+        Diffuse {
+            albedo: Box::new(vec3(1.0, 1.0, 1.0)),
+        }
+    }
+}
+
+/// Reflective surface. `scattering` blurs the reflection, from 0 for a perfect mirror upwards.
+/// This is a cheap approximation, see [`Conductor`] for physically based metals.
+pub struct Metal {
+    /// Fraction of the light that gets reflected, for red, green and blue.
+    pub albedo: Box<dyn Texture>,
+    /// Radius of the ball of random offsets that blurs the reflection.
+    pub scattering: Box<dyn Texture>,
+}
+
+impl Metal {
+    // probability density of the reflection, moved by a random point in a ball of radius
+    // `scattering`, ending up in `direction`. that is the part of the ball the direction goes
+    // through, weighed by the area of the sphere around the hit at each distance.
+    fn ball_pdf(ray: &Ray, hit: &HitRecord, scattering: f32, direction: &Vec3) -> f32 {
+        if dot(direction, &hit.normal) <= 0.0 {
+            return 0.0;
+        }
+        let reflected = reflect(&normalize(&ray.direction), &hit.normal);
+        let radius2 = scattering * scattering;
+        let middle = dot(direction, &reflected);
+        let half2 = radius2 - (1.0 - middle * middle);
+        if half2 <= 0.0 {
+            return 0.0;
+        }
+        let near = (middle - half2.sqrt()).max(0.0);
+        let far = (middle + half2.sqrt()).max(0.0);
+        (far.powi(3) - near.powi(3)) / (4.0 * PI * radius2 * scattering)
+    }
+}
+
+impl Material for Metal {
+    fn sample(&self, ray: &Ray, hit: &HitRecord, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let reflected = reflect(&normalize(&ray.direction), &hit.normal);
+        let u = sampler.get_2d().push(sampler.get_1d());
+        let scattering = scalar_at(&*self.scattering, hit);
+        let direction = normalize(&(reflected + scattering * uniform_ball(&u)));
+        if dot(&direction, &hit.normal) <= 0.0 || !direction.x.is_finite() {
+            return None;
+        }
+        Some(BsdfSample {
+            direction,
+            weight: self.albedo.value(&hit.uv, &hit.point, &hit.normal),
+            pdf: self.pdf(ray, hit, &direction),
+            delta: scattering == 0.0,
+        })
+    }
+
+    fn eval(&self, ray: &Ray, hit: &HitRecord, direction: &Vec3) -> Vec3 {
+        self.albedo.value(&hit.uv, &hit.point, &hit.normal) * self.pdf(ray, hit, direction)
+    }
+
+    fn pdf(&self, ray: &Ray, hit: &HitRecord, direction: &Vec3) -> f32 {
+        let scattering = scalar_at(&*self.scattering, hit);
+        if scattering == 0.0 {
+            return 0.0;
+        }
+        Metal::ball_pdf(ray, hit, scattering, direction)
+    }
+}
+
+/// Metal reflecting light as described by its complex index of refraction, with a GGX
+/// microfacet model for rough surfaces.
+pub struct Conductor {
+    /// Real part of the index of refraction, for red, green and blue.
+    pub eta: Vec3,
+    /// Imaginary part of the index of refraction, the extinction coefficient.
+    pub k: Vec3,
+    /// From 0 for a perfect mirror to 1 for a very rough surface.
+    pub roughness: Box<dyn Texture>,
+}
+
+impl Conductor {
+    /// Gold with the given roughness.
+    pub fn gold(roughness: Box<dyn Texture>) -> Conductor {
+        Conductor {
+            eta: vec3(0.143, 0.374, 1.442),
+            k: vec3(3.983, 2.385, 1.603),
+            roughness,
+        }
+    }
+
+    /// Copper with the given roughness.
+    pub fn copper(roughness: Box<dyn Texture>) -> Conductor {
+        Conductor {
+            eta: vec3(0.200, 0.924, 1.102),
+            k: vec3(3.912, 2.452, 2.142),
+            roughness,
+        }
+    }
+
+    /// Aluminium with the given roughness.
+    pub fn aluminium(roughness: Box<dyn Texture>) -> Conductor {
+        Conductor {
+            eta: vec3(1.657, 0.880, 0.521),
+            k: vec3(9.224, 6.270, 4.837),
+            roughness,
+        }
+    }
+
+    /// Silver with the given roughness.
+    pub fn silver(roughness: Box<dyn Texture>) -> Conductor {
+        Conductor {
+            eta: vec3(0.155, 0.117, 0.138),
+            k: vec3(4.828, 3.122, 2.147),
+            roughness,
+        }
+    }
+
+    fn fresnel(&self, cos_i: f32) -> Vec3 {
+        vec3(
+            fresnel_conductor(cos_i, self.eta.x, self.k.x),
+            fresnel_conductor(cos_i, self.eta.y, self.k.y),
+            fresnel_conductor(cos_i, self.eta.z, self.k.z),
+        )
+    }
+
+    // bsdf times cosine and pdf of the rough reflection
+    fn evaluate(&self, ray: &Ray, hit: &HitRecord, direction: &Vec3) -> (Vec3, f32) {
+        let frame = Frame::new(&hit.normal);
+        let wo = frame.to_local(&-normalize(&ray.direction));
+        let roughness = scalar_at(&*self.roughness, hit);
+        if roughness == 0.0 || wo.z <= 0.0 {
+            return (vec3(0.0, 0.0, 0.0), 0.0);
+        }
+        let ggx = Ggx::from_roughness(roughness);
+        let wi = frame.to_local(direction);
+        ggx_reflection(&ggx, &wo, &wi, |cos| self.fresnel(cos))
+    }
+}
+
+impl Material for Conductor {
+    fn sample(&self, ray: &Ray, hit: &HitRecord, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let frame = Frame::new(&hit.normal);
+        let wo = frame.to_local(&-normalize(&ray.direction));
+        if wo.z <= 0.0 {
+            return None;
+        }
+        // smooth and rough parts of a textured surface take the same numbers from the sampler
+        let u = sampler.get_2d();
+        let roughness = scalar_at(&*self.roughness, hit);
+        if roughness == 0.0 {
+            return Some(BsdfSample {
+                direction: frame.to_world(&vec3(-wo.x, -wo.y, wo.z)),
+                weight: self.fresnel(wo.z),
+                pdf: 0.0,
+                delta: true,
+            });
+        }
+
+        // reflect off a facet that is visible from where the ray came from. the distribution of
+        // facet normals cancels out, leaving only how many of them are shadowed.
+        let ggx = Ggx::from_roughness(roughness);
+        let wi = sample_ggx_reflection(&ggx, &wo, &u)?;
+        let wm = normalize(&(wo + wi));
+        let (_, pdf) = ggx_reflection(&ggx, &wo, &wi, |cos| self.fresnel(cos));
+        Some(BsdfSample {
+            direction: frame.to_world(&wi),
+            weight: self.fresnel(dot(&wo, &wm)) * (ggx.g(&wo, &wi) / ggx.g1(&wo)),
+            pdf,
+            delta: false,
+        })
+    }
+
+    fn eval(&self, ray: &Ray, hit: &HitRecord, direction: &Vec3) -> Vec3 {
+        self.evaluate(ray, hit, direction).0
+    }
+
+    fn pdf(&self, ray: &Ray, hit: &HitRecord, direction: &Vec3) -> f32 {
+        self.evaluate(ray, hit, direction).1
+    }
+}
+
+/// Clear refractive material, like glass or water. Rough surfaces, like frosted glass, use a
+/// GGX microfacet model.
+pub struct Dielectric {
+    /// Index of refraction, relative to the surrounding medium.
+    pub ior: f32,
+    /// From 0 for a perfectly smooth surface to 1 for a very rough one.
+    pub roughness: Box<dyn Texture>,
+}
+
+impl Dielectric {
+    // bsdf times cosine and pdf of rough glass
+    fn evaluate(&self, ray: &Ray, hit: &HitRecord, direction: &Vec3) -> (Vec3, f32) {
+        let frame = Frame::new(&hit.normal);
+        let wo = frame.to_local(&-normalize(&ray.direction));
+        let roughness = scalar_at(&*self.roughness, hit);
+        if roughness == 0.0 || wo.z <= 0.0 {
+            return (vec3(0.0, 0.0, 0.0), 0.0);
+        }
+        let ggx = Ggx::from_roughness(roughness);
+        let wi = frame.to_local(direction);
+        let (value, pdf) = rough_dielectric(&ggx, &wo, &wi, relative_eta(self.ior, hit));
+        (vec3(value, value, value), pdf)
+    }
+}
+
+impl Material for Dielectric {
+    fn sample(&self, ray: &Ray, hit: &HitRecord, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let eta = relative_eta(self.ior, hit);
+        // as for conductors, smooth and rough parts take the same numbers from the sampler
+        let (u, uc) = (sampler.get_2d(), sampler.get_1d());
+        let roughness = scalar_at(&*self.roughness, hit);
+        if roughness > 0.0 {
+            let frame = Frame::new(&hit.normal);
+            let wo = frame.to_local(&-normalize(&ray.direction));
+            if wo.z <= 0.0 {
+                return None;
+            }
+            // as for conductors, only the shadowing of the facets remains
+            let ggx = Ggx::from_roughness(roughness);
+            let wi = sample_rough_dielectric(&ggx, &wo, eta, &u, uc)?;
+            let weight = ggx.g(&wo, &wi) / ggx.g1(&wo);
+            return Some(BsdfSample {
+                direction: frame.to_world(&wi),
+                weight: vec3(weight, weight, weight),
+                pdf: rough_dielectric(&ggx, &wo, &wi, eta).1,
+                delta: false,
+            });
+        }
+
+        let unit_dir = normalize(&ray.direction);
+        let cos_i = dot(&-unit_dir, &hit.normal).min(1.0);
+
+        // randomly choose between reflection and refraction, in proportion to the amount of
+        // light that goes each way.
+        let direction = if uc < fresnel_dielectric(cos_i, eta) {
+            reflect(&unit_dir, &hit.normal)
+        } else {
+            refract(&unit_dir, &hit.normal, eta)
+        };
+        Some(BsdfSample {
+            direction: normalize(&direction),
+            weight: vec3(1.0, 1.0, 1.0),
+            pdf: 0.0,
+            delta: true,
+        })
+    }
+
+    fn eval(&self, ray: &Ray, hit: &HitRecord, direction: &Vec3) -> Vec3 {
+        self.evaluate(ray, hit, direction).0
+    }
+
+    fn pdf(&self, ray: &Ray, hit: &HitRecord, direction: &Vec3) -> f32 {
+        self.evaluate(ray, hit, direction).1
+    }
+}
+
+/// Physically based uber material after the Disney principled BSDF (Burley, 2012 and 2015).
+/// A handful of parameters between 0 and 1 blend its diffuse, specular, sheen, clearcoat and
+/// glass lobes, to cover most real world surfaces with a single material. All specular lobes
+/// use the GGX microfacet model, and get importance sampled along with the diffuse lobe. The
+/// parameters are textures, so they can vary over the surface, with values outside of [0, 1]
+/// clamped.
+pub struct Principled {
+    /// Color of the diffuse lobe for dielectrics, or of the reflection for metals.
+    pub base_color: Box<dyn Texture>,
+    /// Blends from a dielectric at 0 to a metal at 1.
+    pub metallic: Box<dyn Texture>,
+    /// From 0 for a perfectly smooth surface to 1 for a very rough one.
+    pub roughness: Box<dyn Texture>,
+    /// Amount of specular reflection off dielectrics. The default of 0.5 gives the 4%
+    /// reflectance at normal incidence of most common materials.
+    pub specular: Box<dyn Texture>,
+    /// Soft reflection at grazing angles, like that of cloth.
+    pub sheen: Box<dyn Texture>,
+    /// Strength of a second, clear layer of varnish on top.
+    pub clearcoat: Box<dyn Texture>,
+    /// Roughness of the clearcoat layer.
+    pub clearcoat_roughness: Box<dyn Texture>,
+    /// Blends from an opaque dielectric at 0 to glass at 1, which lets light through tinted by
+    /// the base color.
+    pub transmission: Box<dyn Texture>,
+    /// Index of refraction of the glass lobe.
+    pub ior: f32,
+    /// Light given off by the surface, from both of its sides, or None if it gives off none.
+    pub emission: Option<Box<dyn Texture>>,
+}
+
+impl Default for Principled {
+    fn default() -> Self {
+        Principled {
+            base_color: Box::new(vec3(0.8, 0.8, 0.8)),
+            metallic: Box::new(0.0),
+            roughness: Box::new(0.5),
+            specular: Box::new(0.5),
+            sheen: Box::new(0.0),
+            clearcoat: Box::new(0.0),
+            clearcoat_roughness: Box::new(0.03),
+            transmission: Box::new(0.0),
+            ior: 1.5,
+            emission: None,
+        }
+    }
+}
+
+// the lobes of a principled material at a hit point, with the weight of each lobe and the
+// probability of sampling it.
+struct PrincipledLobes {
+    base_color: Vec3,
+    roughness: f32,
+    sheen: f32,
+    clearcoat_roughness: f32,
+    diffuse: f32,
+    specular: f32,
+    // reflectance of the specular lobe at normal incidence
+    specular_f0: Vec3,
+    glass: f32,
+    clearcoat: f32,
+    // probabilities of picking the diffuse, specular, glass and clearcoat lobes
+    pick: [f32; 4],
+    eta: f32,
+}
+
+impl Principled {
+    fn lobes(&self, hit: &HitRecord) -> PrincipledLobes {
+        let base_color = self.base_color.value(&hit.uv, &hit.point, &hit.normal);
+        let parameter = |texture: &dyn Texture| scalar_at(texture, hit).clamp(0.0, 1.0);
+        let metallic = parameter(&*self.metallic);
+        let transmission = parameter(&*self.transmission);
+        let dielectric = 1.0 - metallic;
+        let diffuse = dielectric * (1.0 - transmission);
+        let specular = 1.0 - dielectric * transmission;
+        let f0 = 0.08 * parameter(&*self.specular);
+        let specular_f0 = vec3(f0, f0, f0) * dielectric + base_color * metallic;
+        let glass = dielectric * transmission;
+        let clearcoat = 0.25 * parameter(&*self.clearcoat);
+
+        // sample the lobes about in proportion to how much light they reflect. the specular
+        // lobe reflects much more at grazing angles than its f0 lets on, so give it some extra.
+        let mut pick = [
+            diffuse * luminance(&base_color),
+            specular * luminance(&specular_f0).max(0.25),
+            glass,
+            clearcoat,
+        ];
+        let total: f32 = pick.iter().sum();
+        if total > 0.0 {
+            pick.iter_mut().for_each(|p| *p /= total);
+        } else {
+            pick = [1.0, 0.0, 0.0, 0.0];
+        }
+
+        PrincipledLobes {
+            base_color,
+            roughness: parameter(&*self.roughness),
+            sheen: parameter(&*self.sheen),
+            clearcoat_roughness: parameter(&*self.clearcoat_roughness),
+            diffuse,
+            specular,
+            specular_f0,
+            glass,
+            clearcoat,
+            pick,
+            eta: relative_eta(self.ior, hit),
+        }
+    }
+
+    // bsdf times cosine and pdf of the mixture of all lobes, in the local frame
+    fn mixture(&self, lobes: &PrincipledLobes, wo: &Vec3, wi: &Vec3) -> (Vec3, f32) {
+        let mut value = vec3(0.0, 0.0, 0.0);
+        let mut pdf = 0.0;
+
+        if wi.z > 0.0 && lobes.diffuse > 0.0 {
+            let cos_d = dot(wi, &normalize(&(wo + wi)));
+            let sheen = lobes.sheen * (1.0 - cos_d).max(0.0).powi(5);
+            let f = lobes.base_color / PI + vec3(sheen, sheen, sheen);
+            value += f * (lobes.diffuse * wi.z);
+            pdf += lobes.pick[0] * wi.z / PI;
+        }
+        if lobes.specular > 0.0 {
+            let ggx = Ggx::from_roughness(lobes.roughness);
+            let f0 = lobes.specular_f0;
+            let (v, p) = ggx_reflection(&ggx, wo, wi, |cos| fresnel_schlick(&f0, cos));
+            value += v * lobes.specular;
+            pdf += lobes.pick[1] * p;
+        }
+        if lobes.glass > 0.0 {
+            let ggx = Ggx::from_roughness(lobes.roughness);
+            let (v, p) = rough_dielectric(&ggx, wo, wi, lobes.eta);
+            let tint = if wi.z > 0.0 {
+                vec3(1.0, 1.0, 1.0)
+            } else {
+                lobes.base_color
+            };
+            value += tint * (v * lobes.glass);
+            pdf += lobes.pick[2] * p;
+        }
+        if lobes.clearcoat > 0.0 {
+            let ggx = Ggx::from_roughness(lobes.clearcoat_roughness);
+            let f0 = vec3(0.04, 0.04, 0.04);
+            let (v, p) = ggx_reflection(&ggx, wo, wi, |cos| fresnel_schlick(&f0, cos));
+            value += v * lobes.clearcoat;
+            pdf += lobes.pick[3] * p;
+        }
+        (value, pdf)
+    }
+
+    fn evaluate(&self, ray: &Ray, hit: &HitRecord, direction: &Vec3) -> (Vec3, f32) {
+        let frame = Frame::new(&hit.normal);
+        let wo = frame.to_local(&-normalize(&ray.direction));
+        if wo.z <= 0.0 {
+            return (vec3(0.0, 0.0, 0.0), 0.0);
+        }
+        self.mixture(&self.lobes(hit), &wo, &frame.to_local(direction))
+    }
+}
+
+impl Material for Principled {
+    fn sample(&self, ray: &Ray, hit: &HitRecord, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let frame = Frame::new(&hit.normal);
+        let wo = frame.to_local(&-normalize(&ray.direction));
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let lobes = self.lobes(hit);
+
+        // pick one of the lobes to sample a direction from. every lobe takes the same numbers
+        // from the sampler, so the dimensions of later bounces do not depend on the lobe.
+        let mut choice = sampler.get_1d();
+        let u = sampler.get_2d();
+        let uc = sampler.get_1d();
+        let lobe = lobes
+            .pick
+            .iter()
+            .position(|&p| {
+                choice -= p;
+                choice < 0.0
+            })
+            .unwrap_or(0);
+        let wi = match lobe {
+            0 => Some(cosine_hemisphere(&u)),
+            1 => sample_ggx_reflection(&Ggx::from_roughness(lobes.roughness), &wo, &u),
+            2 => {
+                let ggx = Ggx::from_roughness(lobes.roughness);
+                sample_rough_dielectric(&ggx, &wo, lobes.eta, &u, uc)
+            }
+            _ => {
+                let ggx = Ggx::from_roughness(lobes.clearcoat_roughness);
+                sample_ggx_reflection(&ggx, &wo, &u)
+            }
+        }?;
+
+        // weigh by the pdf of the whole mixture, as any of the lobes could have picked wi
+        let (value, pdf) = self.mixture(&lobes, &wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            direction: frame.to_world(&wi),
+            weight: value / pdf,
+            pdf,
+            delta: false,
+        })
+    }
+
+    fn eval(&self, ray: &Ray, hit: &HitRecord, direction: &Vec3) -> Vec3 {
+        self.evaluate(ray, hit, direction).0
+    }
+
+    fn pdf(&self, ray: &Ray, hit: &HitRecord, direction: &Vec3) -> f32 {
+        self.evaluate(ray, hit, direction).1
+    }
+
+    fn emitted(&self, _ray: &Ray, hit: &HitRecord) -> Vec3 {
+        match &self.emission {
+            Some(emission) => emission.value(&hit.uv, &hit.point, &hit.normal),
+            None => vec3(0.0, 0.0, 0.0),
+        }
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.emission.is_some()
+    }
+}
+
+/// Surface that gives off light, turning whatever object it is on into a light source.
+/// It does not reflect any light, and emits equally from both sides of the surface.
+pub struct Emissive {
+    /// Light given off by the surface, for red, green and blue.
+    pub emission: Box<dyn Texture>,
+}
+
+impl Material for Emissive {
+    fn sample(
+        &self,
+        _ray: &Ray,
+        _hit: &HitRecord,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<BsdfSample> {
+        None
+    }
+
+    fn emitted(&self, _ray: &Ray, hit: &HitRecord) -> Vec3 {
+        self.emission.value(&hit.uv, &hit.point, &hit.normal)
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+}
+
+/// Detail added to a surface by changing its shading normal, without changing its geometry.
+pub enum NormalMap {
+    /// Tangent space normal map, as baked from detailed models. The red, green and blue
+    /// channels map from [0, 1] to [-1, 1] along the tangent, bitangent and normal of the hit.
+    Tangent(Box<dyn Texture>),
+    /// Grayscale height map. The normal tilts along the slope of the heights, with `strength`
+    /// the height of a value of 1 in units of uv.
+    Bump {
+        /// Heights, as the [scalar](Texture::scalar) values of the texture.
+        height: Box<dyn Texture>,
+        /// Height of a value of 1, in units of uv.
+        strength: f32,
+    },
+}
+
+/// Wraps a material to shade it with the normals of a normal or bump map.
+pub struct NormalMapped {
+    /// The material that gets shaded with the mapped normals.
+    pub material: Box<dyn Material>,
+    /// Where the normals come from.
+    pub map: NormalMap,
+}
+
+impl NormalMapped {
+    // the hit with its normal replaced by the mapped one
+    fn mapped<'a>(&self, ray: &Ray, hit: &HitRecord<'a>) -> HitRecord<'a> {
+        let outward = hit.outward_normal();
+        let (t, b) = (&hit.tangent, &hit.bitangent);
+        let normal = match &self.map {
+            NormalMap::Tangent(texture) => {
+                let n = texture.value(&hit.uv, &hit.point, &hit.normal) * 2.0 - vec3(1.0, 1.0, 1.0);
+                t * n.x + b * n.y + outward * n.z
+            }
+            NormalMap::Bump { height, strength } => {
+                // slope of the heights, by stepping a little along the tangent and bitangent
+                let delta = 0.001;
+                let height_at = |du: f32, dv: f32| {
+                    let uv = hit.uv + vec2(du, dv);
+                    let point = hit.point + t * du + b * dv;
+                    height.scalar(&uv, &point, &hit.normal)
+                };
+                let h = height_at(0.0, 0.0);
+                let dhdu = (height_at(delta, 0.0) - h) / delta;
+                let dhdv = (height_at(0.0, delta) - h) / delta;
+                outward - (t * dhdu + b * dhdv) * *strength
+            }
+        };
+        let normal = normalize(&if hit.front_face { normal } else { -normal });
+        // a mapped normal facing away from the ray would shade the surface as seen from
+        // behind, so keep the real normal then
+        if dot(&normal, &ray.direction) >= 0.0 || !normal.x.is_finite() {
+            return *hit;
+        }
+        HitRecord { normal, ..*hit }
+    }
+}
+
+impl Material for NormalMapped {
+    fn sample(&self, ray: &Ray, hit: &HitRecord, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        self.material.sample(ray, &self.mapped(ray, hit), sampler)
+    }
+
+    fn eval(&self, ray: &Ray, hit: &HitRecord, direction: &Vec3) -> Vec3 {
+        self.material.eval(ray, &self.mapped(ray, hit), direction)
+    }
+
+    fn pdf(&self, ray: &Ray, hit: &HitRecord, direction: &Vec3) -> f32 {
+        self.material.pdf(ray, &self.mapped(ray, hit), direction)
+    }
+
+    fn emitted(&self, ray: &Ray, hit: &HitRecord) -> Vec3 {
+        self.material.emitted(ray, hit)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+}
+
+// ratio of the refractive indices on the incident and transmitted sides of a hit on a material
+// with the given ior. when the ray exits the surface it goes from the material back into the
+// surrounding medium, so the ratio is inverted.
+fn relative_eta(ior: f32, hit: &HitRecord) -> f32 {
+    if hit.front_face {
+        1.0 / ior
+    } else {
+        ior
+    }
+}
+
+// the lobes below work in the local frame of the surface, for light scattering from wi towards
+// wo, with wo above the surface. they give the bsdf times the cosine of wi with the normal,
+// along with the pdf of sampling wi.
+
+// samples a reflection off a ggx facet that is visible from wo. the facet may send the ray
+// below the surface, in which case it gets absorbed.
+fn sample_ggx_reflection(ggx: &Ggx, wo: &Vec3, u: &Vec2) -> Option<Vec3> {
+    let wm = ggx.sample_visible(wo, u);
+    let wi = reflect(&-wo, &wm);
+    if wi.z > 0.0 {
+        Some(wi)
+    } else {
+        None
+    }
+}
+
+// reflection off ggx facets, with the fresnel factor given by the cosine of wo with the facet
+// normal.
+fn ggx_reflection(ggx: &Ggx, wo: &Vec3, wi: &Vec3, fresnel: impl Fn(f32) -> Vec3) -> (Vec3, f32) {
+    if wi.z <= 0.0 {
+        return (vec3(0.0, 0.0, 0.0), 0.0);
+    }
+    let wm = normalize(&(wo + wi));
+    let cos_m = dot(wo, &wm);
+    let value = fresnel(cos_m) * (ggx.d(&wm) * ggx.g(wo, wi) / (4.0 * wo.z));
+    (value, ggx.visible_pdf(wo, &wm) / (4.0 * cos_m))
+}
+
+// picks a visible facet of rough glass with u, and reflects off it or refracts through it in
+// proportion to the light going each way, choosing between them with uc. eta is the ratio of
+// refractive indices on the sides of wo and the other side.
+fn sample_rough_dielectric(ggx: &Ggx, wo: &Vec3, eta: f32, u: &Vec2, uc: f32) -> Option<Vec3> {
+    let wm = ggx.sample_visible(wo, u);
+    let wi = if uc < fresnel_dielectric(dot(wo, &wm), eta) {
+        reflect(&-wo, &wm)
+    } else {
+        refract(&-wo, &wm, eta)
+    };
+    // the facet may send the ray to the wrong side of the surface
+    let reflected = dot(wo, &wm) * dot(&wi, &wm) > 0.0;
+    if (wi.z > 0.0) == reflected && wi.z != 0.0 {
+        Some(wi)
+    } else {
+        None
+    }
+}
+
+// reflection and refraction by rough glass (Walter et al., 2007). like smooth glass, this leaves
+// out the scaling of radiance by the squared ratio of refractive indices, which cancels out for
+// rays that both enter and leave an object.
+fn rough_dielectric(ggx: &Ggx, wo: &Vec3, wi: &Vec3, eta: f32) -> (f32, f32) {
+    if wi.z > 0.0 {
+        // reflection gets picked with the probability of the fresnel factor that scales it
+        let (value, pdf) = ggx_reflection(ggx, wo, wi, |cos| {
+            let f = fresnel_dielectric(cos, eta);
+            vec3(f, f, f)
+        });
+        let reflected = fresnel_dielectric(dot(wo, &normalize(&(wo + wi))), eta);
+        return (value.x, pdf * reflected);
+    }
+    // the facet normal that refracts wo into wi
+    let wm = normalize(&(wo * eta + wi));
+    let wm = if wm.z < 0.0 { -wm } else { wm };
+    let (cos_o, cos_i) = (dot(wo, &wm), dot(wi, &wm));
+    if wi.z == 0.0 || cos_o <= 0.0 || cos_i >= 0.0 {
+        return (0.0, 0.0);
+    }
+    let transmitted = 1.0 - fresnel_dielectric(cos_o, eta);
+    let denom = (cos_i + eta * cos_o).powi(2);
+    let value = transmitted * ggx.d(&wm) * ggx.g(wo, wi) * (cos_i * cos_o).abs() / (wo.z * denom);
+    let pdf = transmitted * ggx.visible_pdf(wo, &wm) * cos_i.abs() / denom;
+    (value, pdf)
+}
+
+// schlick's approximation of the fresnel reflectance, for a reflectance of f0 at normal incidence
+fn fresnel_schlick(f0: &Vec3, cos_i: f32) -> Vec3 {
+    let w = (1.0 - cos_i).max(0.0).powi(5);
+    f0 + (vec3(1.0, 1.0, 1.0) - f0) * w
+}
+
+// value of a texture of a parameter that is a single number, at the hit
+fn scalar_at(texture: &dyn Texture, hit: &HitRecord) -> f32 {
+    texture.scalar(&hit.uv, &hit.point, &hit.normal)
+}
+
+pub(crate) fn luminance(color: &Vec3) -> f32 {
+    dot(color, &vec3(0.2126, 0.7152, 0.0722))
+}
+
+// orthonormal frame around a normal, for working with directions relative to the surface
+struct Frame {
+    x: Vec3,
+    y: Vec3,
+    z: Vec3,
+}
+
+impl Frame {
+    fn new(normal: &Vec3) -> Frame {
+        let (x, y) = orthonormal_basis(normal);
+        Frame { x, y, z: *normal }
+    }
+
+    fn to_local(&self, v: &Vec3) -> Vec3 {
+        vec3(dot(v, &self.x), dot(v, &self.y), dot(v, &self.z))
+    }
+
+    fn to_world(&self, v: &Vec3) -> Vec3 {
+        self.x * v.x + self.y * v.y + self.z * v.z
+    }
+}
+
+fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
+    v - 2.0 * dot(v, n) * n
+}
+
+// refracts unit vector v through a surface with unit normal n facing against v, where eta is
+// the ratio of the refractive indices on the incident and transmitted sides.
+// assumes the caller already checked for total internal reflection.
+fn refract(v: &Vec3, n: &Vec3, eta: f32) -> Vec3 {
+    let cos_i = dot(&-v, n).min(1.0);
+    let perpendicular = eta * (v + cos_i * n);
+    let parallel = -(1.0 - glm::length2(&perpendicular)).abs().sqrt() * n;
+    perpendicular + parallel
+}
+
+// fraction of light that gets reflected at the boundary between two dielectrics, for light
+// coming in at an angle with cosine cos_i and with eta the ratio of refractive indices as above.
+// this is the exact fresnel equation for unpolarized light, rather than schlick's approximation.
+fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        // total internal reflection
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_s = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_p = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (r_s * r_s + r_p * r_p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::{IndependentSampler, Sampler};
+    use crate::shapes::Sphere;
+
+    // materials with every kind of lobe that can be sampled, at the given roughness
+    fn materials(roughness: f32) -> Vec<(&'static str, Box<dyn Material>)> {
+        let principled = |configure: fn(&mut Principled)| {
+            let mut material = Principled {
+                roughness: Box::new(roughness),
+                ..Principled::default()
+            };
+            configure(&mut material);
+            Box::new(material) as Box<dyn Material>
+        };
+        vec![
+            ("diffuse", Box::new(Diffuse::default())),
+            (
+                "metal",
+                Box::new(Metal {
+                    albedo: Box::new(vec3(0.9, 0.6, 0.3)),
+                    scattering: Box::new(roughness),
+                }),
+            ),
+            ("conductor", Box::new(Conductor::gold(Box::new(roughness)))),
+            (
+                "dielectric",
+                Box::new(Dielectric {
+                    ior: 1.5,
+                    roughness: Box::new(roughness),
+                }),
+            ),
+            ("principled", principled(|_| ())),
+            (
+                "principled metal",
+                principled(|m| m.metallic = Box::new(1.0)),
+            ),
+            (
+                "principled glass",
+                principled(|m| m.transmission = Box::new(1.0)),
+            ),
+            (
+                "principled coated",
+                principled(|m| {
+                    m.sheen = Box::new(1.0);
+                    m.clearcoat = Box::new(1.0);
+                    m.clearcoat_roughness = Box::new(0.4);
+                    m.transmission = Box::new(0.5);
+                }),
+            ),
+        ]
+    }
+
+    // a ray arriving from `wo`, given relative to the normal of the surface it hits, on the
+    // outside of a sphere, or on its inside for `front` false
+    fn hit_from(wo: Vec3, front: bool, object: &Sphere) -> (Ray, HitRecord<'_>) {
+        let wo = normalize(&if front { wo } else { vec3(wo.x, wo.y, -wo.z) });
+        let point = vec3(0.0, 0.0, 1.0);
+        let ray = Ray::new(point + wo * 2.0, -wo, 0.0);
+        let hit = HitRecord::new(&ray, 2.0, &vec3(0.0, 0.0, 1.0), vec2(0.5, 0.5), object);
+        (ray, hit)
+    }
+
+    fn incoming() -> Vec<(Vec3, bool)> {
+        let directions = [vec3(0.3, 0.1, 0.95), vec3(0.9, 0.0, 0.25)];
+        let sides = [true, false];
+        directions
+            .iter()
+            .flat_map(|&wo| sides.iter().map(move |&front| (wo, front)))
+            .collect()
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() <= 2e-3 * a.abs().max(b.abs()).max(1e-3)
+    }
+
+    #[test]
+    fn sample_weights_are_eval_over_pdf() {
+        let object = Sphere {
+            position: vec3(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: Box::new(Diffuse::default()),
+        };
+        let mut sampler = IndependentSampler::new(7);
+        for (name, material) in materials(0.3) {
+            for (wo, front) in incoming() {
+                let (ray, hit) = hit_from(wo, front, &object);
+                for index in 0..1000 {
+                    sampler.start_pixel_sample([0, 0], index);
+                    let sample = match material.sample(&ray, &hit, &mut sampler) {
+                        Some(sample) if !sample.delta => sample,
+                        _ => continue,
+                    };
+                    let pdf = material.pdf(&ray, &hit, &sample.direction);
+                    let eval = material.eval(&ray, &hit, &sample.direction);
+                    assert!(pdf > 0.0, "{} sampled a direction of zero pdf", name);
+                    assert!(
+                        close(sample.pdf, pdf),
+                        "{}: pdf {} != {}",
+                        name,
+                        sample.pdf,
+                        pdf
+                    );
+                    for i in 0..3 {
+                        let expected = eval[i] / pdf;
+                        assert!(
+                            close(sample.weight[i], expected),
+                            "{}: weight {} != eval / pdf {}, from {:?}",
+                            name,
+                            sample.weight[i],
+                            expected,
+                            wo
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pdfs_integrate_to_the_chance_of_sampling() {
+        let object = Sphere {
+            position: vec3(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: Box::new(Diffuse::default()),
+        };
+        let mut sampler = IndependentSampler::new(9);
+        // integrates over the sphere of directions on a grid in the cosine and the angle around
+        // the normal, in which solid angle is uniform
+        let (rows, columns) = (100, 200);
+        for (name, material) in materials(0.5) {
+            for (wo, front) in incoming() {
+                let (ray, hit) = hit_from(wo, front, &object);
+                let mut integral = 0.0;
+                for row in 0..rows {
+                    let z = 2.0 * (row as f32 + 0.5) / rows as f32 - 1.0;
+                    let r = (1.0 - z * z).sqrt();
+                    for column in 0..columns {
+                        let phi = 2.0 * PI * (column as f32 + 0.5) / columns as f32;
+                        let direction = vec3(r * phi.cos(), r * phi.sin(), z);
+                        integral += material.pdf(&ray, &hit, &direction) as f64;
+                    }
+                }
+                let integral = integral * 4.0 * PI as f64 / (rows * columns) as f64;
+
+                // the pdf leaves out the directions that sampling fails to find
+                let tries = 10000;
+                let sampled = (0..tries)
+                    .filter(|&index| {
+                        sampler.start_pixel_sample([0, 0], index);
+                        material.sample(&ray, &hit, &mut sampler).is_some()
+                    })
+                    .count();
+                let chance = sampled as f64 / tries as f64;
+                assert!(integral <= 1.01, "{}: pdf integrates to {}", name, integral);
+                assert!(
+                    (integral - chance).abs() < 0.02,
+                    "{}: pdf integrates to {}, but {} of the samples succeed, from {:?}",
+                    name,
+                    integral,
+                    chance,
+                    wo
+                );
+            }
+        }
+    }
+}
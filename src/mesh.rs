@@ -0,0 +1,491 @@
+//! Triangles, either on their own or as meshes whose triangles share their vertices.
+
+use crate::bvh::{Aabb, Bvh};
+use crate::light::{area_to_solid_angle, SurfaceSample};
+use crate::{HitRecord, Material, Ray, SceneObject};
+use glm::{dot, normalize, vec2, Vec2, Vec3};
+
+// result of a ray-triangle intersection: distance along the ray and the barycentric weights
+// of the three vertices.
+struct TriangleIntersection {
+    t: f32,
+    b: [f32; 3],
+}
+
+// watertight ray-triangle intersection (Woop, Benthin and Wald, 2013).
+// the vertices are transformed into a space where the ray starts at the origin and points
+// along the z axis, which reduces the test to a 2D edge function test. rays that pass exactly
+// through an edge shared by two triangles are guaranteed to hit exactly one of them, so meshes
+// get neither holes nor double hits along their edges.
+fn intersect_triangle(
+    ray: &Ray,
+    p0: &Vec3,
+    p1: &Vec3,
+    p2: &Vec3,
+    t_min: f32,
+    t_max: f32,
+) -> Option<TriangleIntersection> {
+    let dir = &ray.direction;
+
+    // permute the axes so that z is the dimension in which the ray direction is largest,
+    // keeping the winding order intact.
+    let kz = glm::abs(dir).imax();
+    let mut kx = (kz + 1) % 3;
+    let mut ky = (kx + 1) % 3;
+    if dir[kz] < 0.0 {
+        std::mem::swap(&mut kx, &mut ky);
+    }
+
+    // shear constants which align the ray direction with the z axis
+    let sx = dir[kx] / dir[kz];
+    let sy = dir[ky] / dir[kz];
+    let sz = 1.0 / dir[kz];
+
+    let a = p0 - ray.origin;
+    let b = p1 - ray.origin;
+    let c = p2 - ray.origin;
+    let ax = a[kx] - sx * a[kz];
+    let ay = a[ky] - sy * a[kz];
+    let bx = b[kx] - sx * b[kz];
+    let by = b[ky] - sy * b[kz];
+    let cx = c[kx] - sx * c[kz];
+    let cy = c[ky] - sy * c[kz];
+
+    // scaled barycentric coordinates, as edge function values
+    let mut u = cx * by - cy * bx;
+    let mut v = ax * cy - ay * cx;
+    let mut w = bx * ay - by * ax;
+
+    // when the ray passes (very nearly) through an edge, single precision can not tell on
+    // which side of it we are, so recompute those cases in double precision.
+    if u == 0.0 || v == 0.0 || w == 0.0 {
+        let (ax, ay) = (f64::from(ax), f64::from(ay));
+        let (bx, by) = (f64::from(bx), f64::from(by));
+        let (cx, cy) = (f64::from(cx), f64::from(cy));
+        u = (cx * by - cy * bx) as f32;
+        v = (ax * cy - ay * cx) as f32;
+        w = (bx * ay - by * ax) as f32;
+    }
+
+    // the ray misses if the edge functions do not all have the same sign
+    if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+        return None;
+    }
+    let det = u + v + w;
+    if det == 0.0 {
+        return None;
+    }
+
+    // a ray exactly on an edge belongs to only one of the two triangles sharing it. they run
+    // along the edge in opposite directions, once turned to face the same way, so take the
+    // triangle for which it points one way (like the top-left rule of rasterizers).
+    let owns_edge = |(x0, y0): (f32, f32), (x1, y1): (f32, f32)| {
+        let (ex, ey) = if det > 0.0 {
+            (x1 - x0, y1 - y0)
+        } else {
+            (x0 - x1, y0 - y1)
+        };
+        ey > 0.0 || (ey == 0.0 && ex < 0.0)
+    };
+    if (u == 0.0 && !owns_edge((bx, by), (cx, cy)))
+        || (v == 0.0 && !owns_edge((cx, cy), (ax, ay)))
+        || (w == 0.0 && !owns_edge((ax, ay), (bx, by)))
+    {
+        return None;
+    }
+
+    // scaled hit distance, which we can check against the ray interval before dividing
+    let az = sz * a[kz];
+    let bz = sz * b[kz];
+    let cz = sz * c[kz];
+    let t = (u * az + v * bz + w * cz) / det;
+    if t > t_max || t < t_min {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    Some(TriangleIntersection {
+        t,
+        b: [u * inv_det, v * inv_det, w * inv_det],
+    })
+}
+
+// builds the hit record for an intersection, interpolating the per-vertex attributes.
+// without vertex normals the geometric normal is used for shading, and without texture
+// coordinates the barycentric coordinates serve as uv. the tangent frame follows the uvs
+// across the triangle.
+fn triangle_hit_record<'a>(
+    ray: &Ray,
+    hit: &TriangleIntersection,
+    positions: [&Vec3; 3],
+    normals: Option<[&Vec3; 3]>,
+    uvs: Option<[&Vec2; 3]>,
+    object: &'a dyn SceneObject,
+) -> HitRecord<'a> {
+    let [b0, b1, b2] = hit.b;
+    let [p0, p1, p2] = positions;
+    let geometric_normal = normalize(&triangle_cross(p0, p1, p2));
+    let shading_normal = normals.map(|[n0, n1, n2]| normalize(&(n0 * b0 + n1 * b1 + n2 * b2)));
+    // the vertex normals tell which side is out, whichever way round the vertices are listed
+    let geometric_normal = match shading_normal {
+        Some(n) if dot(&n, &geometric_normal) < 0.0 => -geometric_normal,
+        _ => geometric_normal,
+    };
+    let (uv, (dpdu, dpdv)) = match uvs {
+        Some([uv0, uv1, uv2]) => (
+            uv0 * b0 + uv1 * b1 + uv2 * b2,
+            triangle_tangents(p0, p1, p2, uv0, uv1, uv2),
+        ),
+        None => (vec2(b1, b2), (p1 - p0, p2 - p0)),
+    };
+    let record = HitRecord::new(ray, hit.t, &geometric_normal, uv, object);
+    match shading_normal {
+        Some(n) => record.with_shading_normal(&n),
+        None => record,
+    }
+    .with_tangents(&dpdu, &dpdv)
+}
+
+// derivatives of the point on the triangle with respect to u and v. infinite if the uvs of the
+// triangle are degenerate.
+fn triangle_tangents(
+    p0: &Vec3,
+    p1: &Vec3,
+    p2: &Vec3,
+    uv0: &Vec2,
+    uv1: &Vec2,
+    uv2: &Vec2,
+) -> (Vec3, Vec3) {
+    let (e1, e2): (Vec3, Vec3) = (p1 - p0, p2 - p0);
+    let (d1, d2): (Vec2, Vec2) = (uv1 - uv0, uv2 - uv0);
+    let inv_det = 1.0 / (d1.x * d2.y - d1.y * d2.x);
+    (
+        (e1 * d2.y - e2 * d1.y) * inv_det,
+        (e2 * d1.x - e1 * d2.x) * inv_det,
+    )
+}
+
+fn triangle_bounds(p0: &Vec3, p1: &Vec3, p2: &Vec3) -> Aabb {
+    Aabb::new(*p0, *p0).grow(p1).grow(p2)
+}
+
+// cross product of two edges, whose length is twice the area of the triangle
+fn triangle_cross(p0: &Vec3, p1: &Vec3, p2: &Vec3) -> Vec3 {
+    let e1: Vec3 = p1 - p0;
+    let e2: Vec3 = p2 - p0;
+    e1.cross(&e2)
+}
+
+fn triangle_area(p0: &Vec3, p1: &Vec3, p2: &Vec3) -> f32 {
+    0.5 * glm::length(&triangle_cross(p0, p1, p2))
+}
+
+// picks a point uniformly distributed over the area of the triangle. the pdf is converted to
+// solid angle as seen from `origin`, with `total_area` the area of all triangles the sample
+// could have been taken from.
+fn sample_triangle(
+    origin: &Vec3,
+    p0: &Vec3,
+    p1: &Vec3,
+    p2: &Vec3,
+    u: &Vec2,
+    total_area: f32,
+) -> Option<SurfaceSample> {
+    let su = u.x.sqrt();
+    let b0 = 1.0 - su;
+    let b1 = u.y * su;
+    let b2 = 1.0 - b0 - b1;
+    let point = p0 * b0 + p1 * b1 + p2 * b2;
+    let normal = normalize(&triangle_cross(p0, p1, p2));
+    let pdf = area_to_solid_angle(1.0 / total_area, origin, &point, &normal);
+    if pdf > 0.0 {
+        Some(SurfaceSample {
+            point,
+            normal,
+            uv: vec2(b1, b2),
+            pdf,
+        })
+    } else {
+        None
+    }
+}
+
+/// A single triangle, with optional per-vertex normals and texture coordinates.
+pub struct Triangle {
+    /// Corners of the triangle.
+    pub vertices: [Vec3; 3],
+    /// Normal at each vertex, interpolated across the triangle for smooth shading.
+    pub normals: Option<[Vec3; 3]>,
+    /// Texture coordinates of each vertex.
+    pub uvs: Option<[Vec2; 3]>,
+    /// Material of the triangle.
+    pub material: Box<dyn Material>,
+}
+
+impl SceneObject for Triangle {
+    fn ray_hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let [p0, p1, p2] = &self.vertices;
+        let hit = intersect_triangle(ray, p0, p1, p2, t_min, t_max)?;
+        Some(triangle_hit_record(
+            ray,
+            &hit,
+            [p0, p1, p2],
+            self.normals.as_ref().map(|[n0, n1, n2]| [n0, n1, n2]),
+            self.uvs.as_ref().map(|[uv0, uv1, uv2]| [uv0, uv1, uv2]),
+            self,
+        ))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let [p0, p1, p2] = &self.vertices;
+        Some(triangle_bounds(p0, p1, p2))
+    }
+
+    fn get_material(&self) -> &dyn Material {
+        self.material.as_ref()
+    }
+
+    fn sample(&self, origin: &Vec3, u: &Vec2, _time: f32) -> Option<SurfaceSample> {
+        let [p0, p1, p2] = &self.vertices;
+        sample_triangle(origin, p0, p1, p2, u, triangle_area(p0, p1, p2))
+    }
+
+    fn light_pdf(&self, origin: &Vec3, hit: &HitRecord, _time: f32) -> f32 {
+        let [p0, p1, p2] = &self.vertices;
+        area_to_solid_angle(
+            1.0 / triangle_area(p0, p1, p2),
+            origin,
+            &hit.point,
+            &hit.geometric_normal,
+        )
+    }
+}
+
+/// Indexed triangle mesh. Vertex attributes are shared between the triangles that use them,
+/// and the triangles are kept in a bvh of their own, so that the whole mesh appears as a single
+/// object to the scene.
+pub struct TriangleMesh {
+    positions: Vec<Vec3>,
+    // either empty, or one normal per position
+    normals: Vec<Vec3>,
+    // either empty, or one texture coordinate per position
+    uvs: Vec<Vec2>,
+    indices: Vec<[usize; 3]>,
+    material: Box<dyn Material>,
+    bounds: Aabb,
+    bvh: Bvh,
+    // running total of the triangle areas, for picking triangles in proportion to their area
+    area_cdf: Vec<f32>,
+}
+
+impl TriangleMesh {
+    /// Panics if an index is out of range, or if normals or uvs are given but their count does
+    /// not match the number of positions.
+    pub fn new(
+        positions: Vec<Vec3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<Vec2>,
+        indices: Vec<[usize; 3]>,
+        material: Box<dyn Material>,
+    ) -> TriangleMesh {
+        assert!(normals.is_empty() || normals.len() == positions.len());
+        assert!(uvs.is_empty() || uvs.len() == positions.len());
+        assert!(indices.iter().flatten().all(|&i| i < positions.len()));
+
+        let triangle_bounds: Vec<Aabb> = indices
+            .iter()
+            .map(|&[i0, i1, i2]| triangle_bounds(&positions[i0], &positions[i1], &positions[i2]))
+            .collect();
+        // an empty mesh gets a degenerate box, so it can still be placed in the scene bvh
+        let bounds = if triangle_bounds.is_empty() {
+            Aabb::new(Vec3::zeros(), Vec3::zeros())
+        } else {
+            triangle_bounds
+                .iter()
+                .fold(Aabb::empty(), |acc, b| acc.union(b))
+        };
+        let bvh = Bvh::new(&triangle_bounds);
+        let area_cdf = indices
+            .iter()
+            .scan(0.0, |total, &[i0, i1, i2]| {
+                *total += triangle_area(&positions[i0], &positions[i1], &positions[i2]);
+                Some(*total)
+            })
+            .collect();
+        TriangleMesh {
+            positions,
+            normals,
+            uvs,
+            indices,
+            material,
+            bounds,
+            bvh,
+            area_cdf,
+        }
+    }
+
+    /// Replaces the material of the whole mesh.
+    pub fn with_material(self, material: Box<dyn Material>) -> TriangleMesh {
+        TriangleMesh { material, ..self }
+    }
+
+    fn triangle_hit(
+        &self,
+        index: usize,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRecord<'_>> {
+        let [i0, i1, i2] = self.indices[index];
+        let (p0, p1, p2) = (
+            &self.positions[i0],
+            &self.positions[i1],
+            &self.positions[i2],
+        );
+        let hit = intersect_triangle(ray, p0, p1, p2, t_min, t_max)?;
+        let normals = if self.normals.is_empty() {
+            None
+        } else {
+            Some([&self.normals[i0], &self.normals[i1], &self.normals[i2]])
+        };
+        let uvs = if self.uvs.is_empty() {
+            None
+        } else {
+            Some([&self.uvs[i0], &self.uvs[i1], &self.uvs[i2]])
+        };
+        Some(triangle_hit_record(
+            ray,
+            &hit,
+            [p0, p1, p2],
+            normals,
+            uvs,
+            self,
+        ))
+    }
+
+    fn total_area(&self) -> f32 {
+        self.area_cdf.last().cloned().unwrap_or(0.0)
+    }
+}
+
+impl SceneObject for TriangleMesh {
+    fn ray_hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        self.bvh.hit(ray, t_min, t_max, |i, closest| {
+            self.triangle_hit(i, ray, t_min, closest)
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+
+    fn get_material(&self) -> &dyn Material {
+        self.material.as_ref()
+    }
+
+    fn sample(&self, origin: &Vec3, u: &Vec2, _time: f32) -> Option<SurfaceSample> {
+        let total_area = self.total_area();
+        if total_area <= 0.0 {
+            return None;
+        }
+        // pick a triangle in proportion to its area, then reuse the part of u.x within the
+        // range of that triangle for picking a point on it. triangles without area have an
+        // empty range, which the search skips, even when it ends up at the very end through
+        // rounding.
+        let target = u.x * total_area;
+        let last = self.area_cdf.partition_point(|&c| c < total_area);
+        let index = self.area_cdf.partition_point(|&c| c <= target).min(last);
+        let start = if index > 0 {
+            self.area_cdf[index - 1]
+        } else {
+            0.0
+        };
+        let area = self.area_cdf[index] - start;
+        let u = vec2(((target - start) / area).clamp(0.0, 1.0), u.y);
+
+        let [i0, i1, i2] = self.indices[index];
+        let (p0, p1, p2) = (
+            &self.positions[i0],
+            &self.positions[i1],
+            &self.positions[i2],
+        );
+        sample_triangle(origin, p0, p1, p2, &u, total_area)
+    }
+
+    fn light_pdf(&self, origin: &Vec3, hit: &HitRecord, _time: f32) -> f32 {
+        let total_area = self.total_area();
+        if total_area <= 0.0 {
+            return 0.0;
+        }
+        area_to_solid_angle(1.0 / total_area, origin, &hit.point, &hit.geometric_normal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glm::vec3;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    // rays through points on the edge that two triangles share, which must hit exactly one of
+    // them, however the triangles are wound. where the quad folds, rays that see one triangle
+    // from the front and the other from the back graze a silhouette, and may hit both.
+    fn check_shared_edge(quad: [Vec3; 4], rng: &mut SmallRng) {
+        let [p0, p1, p2, p3] = quad;
+        let (n1, n2) = (triangle_cross(&p0, &p1, &p2), triangle_cross(&p0, &p2, &p3));
+        let windings = [
+            ([p0, p1, p2], [p0, p2, p3]),
+            ([p0, p1, p2], [p0, p3, p2]),
+            ([p1, p2, p0], [p2, p3, p0]),
+        ];
+        let hits = |ray: &Ray, triangles: &([Vec3; 3], [Vec3; 3])| {
+            [triangles.0, triangles.1]
+                .iter()
+                .filter(|[a, b, c]| intersect_triangle(ray, a, b, c, 0.0, f32::INFINITY).is_some())
+                .count()
+        };
+        for triangles in &windings {
+            // straight down onto the edge, which lands on it exactly
+            for i in 1..16 {
+                let point = p0 + (p2 - p0) * (i as f32 / 16.0);
+                for &up in &[1.0, -1.0] {
+                    let ray = Ray::new(point + vec3(0.0, 0.0, up), vec3(0.0, 0.0, -up), 0.0);
+                    assert_eq!(hits(&ray, triangles), 1, "{:?} for {:?}", ray, triangles);
+                }
+            }
+            for _ in 0..2000 {
+                let point = p0 + (p2 - p0) * rng.gen_range(0.01, 0.99);
+                let origin = vec3(
+                    rng.gen_range(-5.0, 5.0),
+                    rng.gen_range(-5.0, 5.0),
+                    rng.gen_range(0.5, 5.0),
+                );
+                let ray = Ray::new(origin, point - origin, 0.0);
+                if dot(&ray.direction, &n1) * dot(&ray.direction, &n2) <= 0.0 {
+                    continue;
+                }
+                assert_eq!(hits(&ray, triangles), 1, "{:?} for {:?}", ray, triangles);
+            }
+        }
+    }
+
+    #[test]
+    fn shared_edges_are_hit_exactly_once() {
+        let mut rng = SmallRng::seed_from_u64(4);
+        let square = [
+            vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+            vec3(1.0, 1.0, 0.0),
+            vec3(0.0, 1.0, 0.0),
+        ];
+        check_shared_edge(square, &mut rng);
+        let skewed = [
+            vec3(-0.3, 0.1, 0.2),
+            vec3(1.7, -0.4, 0.1),
+            vec3(0.9, 1.3, -0.3),
+            vec3(-0.8, 0.9, 0.4),
+        ];
+        check_shared_edge(skewed, &mut rng);
+    }
+}
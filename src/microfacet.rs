@@ -0,0 +1,153 @@
+//! Microfacet theory describes rough surfaces as made up of tiny perfectly smooth facets, with
+//! their normals spread around the surface normal. How light scatters then follows from the
+//! distribution of those normals, and from how much the facets shadow and mask each other.
+//!
+//! Directions here are in a local frame where the surface normal is the z axis.
+
+use glm::{normalize, vec3, Vec2, Vec3};
+use std::f32::consts::PI;
+
+/// The GGX (Trowbridge-Reitz) distribution of facet normals, with Smith's height correlated
+/// shadowing and masking (Walter et al., 2007; Heitz, 2014).
+#[derive(Clone, Copy, Debug)]
+pub struct Ggx {
+    /// Width of the distribution, from near 0 for a nearly smooth surface up to about 1.
+    pub alpha: f32,
+}
+
+impl Ggx {
+    /// Distribution for the perceptual roughness between 0 and 1 that materials are given in,
+    /// which maps to alpha as its square so the look changes about evenly along the range.
+    pub fn from_roughness(roughness: f32) -> Ggx {
+        Ggx {
+            alpha: (roughness * roughness).max(1e-4),
+        }
+    }
+
+    /// Density of facets with normal `wm`, per unit of surface area and of solid angle.
+    pub fn d(&self, wm: &Vec3) -> f32 {
+        if wm.z <= 0.0 {
+            return 0.0;
+        }
+        // written out like this rather than in terms of cos^2 alone, which loses all precision
+        // for the narrow distributions of nearly smooth surfaces
+        let alpha2 = self.alpha * self.alpha;
+        let d = wm.x * wm.x + wm.y * wm.y + wm.z * wm.z * alpha2;
+        alpha2 / (PI * d * d)
+    }
+
+    // smith's auxiliary function, from which masking follows
+    fn lambda(&self, w: &Vec3) -> f32 {
+        let cos2 = w.z * w.z;
+        if cos2 == 0.0 {
+            return f32::INFINITY;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        0.5 * ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0)
+    }
+
+    /// Fraction of the facets facing direction `w` that are visible from there.
+    pub fn g1(&self, w: &Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Fraction of the facets that are visible from both `wo` and `wi`.
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Samples a facet normal as seen from `wo`, in proportion to how much of the view it
+    /// takes up (Heitz, 2018). `wo` must be above the surface, and `u` is uniformly distributed
+    /// in the unit square.
+    pub fn sample_visible(&self, wo: &Vec3, u: &Vec2) -> Vec3 {
+        // stretch the view direction, so that the facets form a hemisphere
+        let wh = normalize(&vec3(self.alpha * wo.x, self.alpha * wo.y, wo.z));
+        let length2 = wh.x * wh.x + wh.y * wh.y;
+        let t1 = if length2 > 0.0 {
+            vec3(-wh.y, wh.x, 0.0) / length2.sqrt()
+        } else {
+            vec3(1.0, 0.0, 0.0)
+        };
+        let t2 = wh.cross(&t1);
+
+        // pick a point on the disk of the projected hemisphere, of which a part is hidden
+        let r = u.x.sqrt();
+        let phi = 2.0 * PI * u.y;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + wh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let nh = t1 * p1 + t2 * p2 + wh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+        // and unstretch the normal found there
+        normalize(&vec3(self.alpha * nh.x, self.alpha * nh.y, nh.z.max(1e-6)))
+    }
+
+    /// Probability density of `sample_visible` picking the facet normal `wm`, seen from `wo`.
+    pub fn visible_pdf(&self, wo: &Vec3, wm: &Vec3) -> f32 {
+        self.g1(wo) * wo.dot(wm).max(0.0) * self.d(wm) / wo.z
+    }
+}
+
+/// Fraction of light reflected by a conductor with complex index of refraction `eta + ik`,
+/// for light coming in at an angle with cosine `cos_i`. This is the exact fresnel equation for
+/// unpolarized light, written in real numbers.
+pub fn fresnel_conductor(cos_i: f32, eta: f32, k: f32) -> f32 {
+    let cos2 = cos_i.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let t2 = 2.0 * a * cos_i;
+    let r_s = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let r_p = r_s * (t3 - t4) / (t3 + t4);
+    0.5 * (r_s + r_p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // integrates f over the hemisphere above the surface, on a grid in the cosine and the
+    // angle around the normal, in which solid angle is uniform
+    fn integrate(f: impl Fn(&Vec3) -> f32) -> f32 {
+        let (rows, columns) = (1000, 200);
+        let mut sum = 0.0;
+        for row in 0..rows {
+            let z = (row as f32 + 0.5) / rows as f32;
+            let r = (1.0 - z * z).sqrt();
+            for column in 0..columns {
+                let phi = 2.0 * PI * (column as f32 + 0.5) / columns as f32;
+                sum += f(&vec3(r * phi.cos(), r * phi.sin(), z)) as f64;
+            }
+        }
+        (sum * 2.0 * PI as f64 / (rows * columns) as f64) as f32
+    }
+
+    #[test]
+    fn projected_facet_area_is_one() {
+        for &roughness in &[0.5, 0.7, 1.0] {
+            let ggx = Ggx::from_roughness(roughness);
+            let area = integrate(|wm| ggx.d(wm) * wm.z);
+            assert!((area - 1.0).abs() < 0.01, "{} for {}", area, roughness);
+        }
+    }
+
+    #[test]
+    fn visible_pdf_integrates_to_one() {
+        for &roughness in &[0.5, 0.7, 1.0] {
+            let ggx = Ggx::from_roughness(roughness);
+            for wo in [
+                vec3(0.0, 0.0, 1.0),
+                vec3(0.6, 0.0, 0.8),
+                vec3(0.3, 0.9, 0.1),
+            ] {
+                let wo = normalize(&wo);
+                let total = integrate(|wm| ggx.visible_pdf(&wo, wm));
+                assert!((total - 1.0).abs() < 0.01, "{} for {:?}", total, wo);
+            }
+        }
+    }
+}
@@ -0,0 +1,340 @@
+//! Objects that move over time. Rays carry the time at which they travel through the scene, and
+//! a moving object is hit wherever it is at that moment. Spreading the rays of a pixel over the
+//! camera's shutter interval then blurs anything that moves.
+
+use crate::bvh::Aabb;
+use crate::light::{area_to_solid_angle, SurfaceSample};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::scene::{HitRecord, SceneObject};
+use glm::{dot, normalize, vec3, Quat, Vec2, Vec3};
+
+/// Placement of an object relative to where it is at rest: scaled along the axes, then rotated
+/// around the origin, then translated.
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    /// Offset added after scaling and rotating.
+    pub translation: Vec3,
+    /// Unit quaternion.
+    pub rotation: Quat,
+    /// Scale along each axis, none of which may be zero.
+    pub scale: Vec3,
+}
+
+impl Transform {
+    /// Transform leaving everything where it is.
+    pub fn identity() -> Transform {
+        Transform::translation(vec3(0.0, 0.0, 0.0))
+    }
+
+    /// Transform moving everything by `offset`.
+    pub fn translation(offset: Vec3) -> Transform {
+        Transform {
+            translation: offset,
+            rotation: glm::quat_identity(),
+            scale: vec3(1.0, 1.0, 1.0),
+        }
+    }
+
+    /// Sets the rotation to turn counterclockwise around the axis, by an angle in degrees.
+    pub fn with_rotation(self, axis: &Vec3, degrees: f32) -> Self {
+        Transform {
+            rotation: glm::quat_angle_axis(degrees.to_radians(), &normalize(axis)),
+            ..self
+        }
+    }
+
+    /// Sets the scale along each axis.
+    pub fn with_scale(self, scale: Vec3) -> Self {
+        Transform { scale, ..self }
+    }
+
+    /// Blends from `self` at 0 to `other` at 1. Rotations take the shortest way around, so they
+    /// blend by less than half a turn.
+    pub fn lerp(&self, other: &Transform, s: f32) -> Transform {
+        Transform {
+            translation: glm::lerp(&self.translation, &other.translation, s),
+            rotation: slerp(&self.rotation, &other.rotation, s),
+            scale: glm::lerp(&self.scale, &other.scale, s),
+        }
+    }
+
+    /// Transforms a point.
+    pub fn point(&self, p: &Vec3) -> Vec3 {
+        self.translation + self.vector(p)
+    }
+
+    /// Transforms a direction or offset, which does not get translated.
+    pub fn vector(&self, v: &Vec3) -> Vec3 {
+        glm::quat_rotate_vec3(&self.rotation, &v.component_mul(&self.scale))
+    }
+
+    /// Transforms a unit normal, keeping it perpendicular to the transformed surface.
+    pub fn normal(&self, n: &Vec3) -> Vec3 {
+        normalize(&glm::quat_rotate_vec3(
+            &self.rotation,
+            &n.component_div(&self.scale),
+        ))
+    }
+
+    /// Undoes [`point`](Transform::point).
+    pub fn inverse_point(&self, p: &Vec3) -> Vec3 {
+        self.inverse_vector(&(p - self.translation))
+    }
+
+    /// Undoes [`vector`](Transform::vector).
+    pub fn inverse_vector(&self, v: &Vec3) -> Vec3 {
+        glm::quat_rotate_vec3(&glm::quat_conjugate(&self.rotation), v).component_div(&self.scale)
+    }
+
+    /// Undoes [`normal`](Transform::normal).
+    pub fn inverse_normal(&self, n: &Vec3) -> Vec3 {
+        let rotated = glm::quat_rotate_vec3(&glm::quat_conjugate(&self.rotation), n);
+        normalize(&rotated.component_mul(&self.scale))
+    }
+
+    // factor by which areas on a surface with the given unit normal grow (nanson's formula)
+    fn area_scale(&self, n: &Vec3) -> f32 {
+        let scale = &self.scale;
+        (scale.x * scale.y * scale.z).abs() * glm::length(&n.component_div(scale))
+    }
+}
+
+// spherical linear interpolation, the shortest way around
+fn slerp(a: &Quat, b: &Quat, s: f32) -> Quat {
+    let cos = glm::quat_dot(a, b);
+    let (b, cos) = if cos < 0.0 { (-b, -cos) } else { (*b, cos) };
+    if cos > 0.9995 {
+        // nearly the same, where the angle is too small to divide by
+        return glm::quat_normalize(&(a * (1.0 - s) + b * s));
+    }
+    let angle = cos.acos();
+    (a * ((1.0 - s) * angle).sin() + b * (s * angle).sin()) / angle.sin()
+}
+
+// angle in radians of the rotation that takes one unit quaternion to the other
+fn angle_between(a: &Quat, b: &Quat) -> f32 {
+    2.0 * glm::quat_dot(a, b).abs().min(1.0).acos()
+}
+
+/// Transform of an object at a point in time.
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe {
+    /// Moment of the keyframe.
+    pub time: f32,
+    /// Where the object is at that moment.
+    pub transform: Transform,
+}
+
+/// How an object moves over time, relative to where it is at rest.
+#[derive(Clone, Debug)]
+pub enum Motion {
+    /// Moves at constant speed, starting at rest at time `start` and ending up transformed by
+    /// `transform` at time `end`. Before and after, the object stands still.
+    Linear {
+        /// Moment the object starts moving.
+        start: f32,
+        /// Moment the object stops moving.
+        end: f32,
+        /// Where the object ends up.
+        transform: Transform,
+    },
+    /// Moves from one keyframe to the next at constant speed, staying at the first and last
+    /// keyframe before and after them. The keyframes are sorted by time.
+    Keyframes(Vec<Keyframe>),
+}
+
+impl Motion {
+    /// Transform of the object at the given time.
+    pub fn transform_at(&self, time: f32) -> Transform {
+        match self {
+            Motion::Linear {
+                start,
+                end,
+                transform,
+            } => {
+                let s = if end > start {
+                    ((time - start) / (end - start)).clamp(0.0, 1.0)
+                } else if time >= *end {
+                    1.0
+                } else {
+                    0.0
+                };
+                Transform::identity().lerp(transform, s)
+            }
+            Motion::Keyframes(keyframes) => {
+                let i = keyframes.partition_point(|k| k.time <= time);
+                if i == 0 {
+                    keyframes[0].transform
+                } else if i == keyframes.len() {
+                    keyframes[i - 1].transform
+                } else {
+                    let (k0, k1) = (&keyframes[i - 1], &keyframes[i]);
+                    let s = (time - k0.time) / (k1.time - k0.time);
+                    k0.transform.lerp(&k1.transform, s)
+                }
+            }
+        }
+    }
+
+    // box containing the given bounds everywhere along the path. rotations sweep the corners
+    // along arcs, so the path is followed in small steps, with the boxes at each step grown by
+    // how far any point of the bounds can move within a step.
+    fn bounds(&self, bounds: &Aabb) -> Aabb {
+        const STEPS: usize = 64;
+        let times = match self {
+            Motion::Linear { start, end, .. } => vec![*start, *end],
+            Motion::Keyframes(keyframes) => keyframes.iter().map(|k| k.time).collect(),
+        };
+        let mut steps = vec![times[0]];
+        for pair in times.windows(2) {
+            steps.extend(
+                (1..=STEPS).map(|i| glm::lerp_scalar(pair[0], pair[1], i as f32 / STEPS as f32)),
+            );
+        }
+
+        // furthest any point of the bounds is from the origin that the object rotates around
+        let radius = glm::length(&glm::max2(&bounds.min.abs(), &bounds.max.abs()));
+        let transforms: Vec<Transform> = steps.iter().map(|&t| self.transform_at(t)).collect();
+        let mut result = Aabb::empty();
+        for (i, transform) in transforms.iter().enumerate() {
+            let mut moved = 0.0f32;
+            for neighbour in [i.wrapping_sub(1), i + 1] {
+                if let Some(other) = transforms.get(neighbour) {
+                    let scale =
+                        glm::comp_max(&glm::max2(&transform.scale.abs(), &other.scale.abs()));
+                    let scaling = glm::comp_max(&(transform.scale - other.scale).abs());
+                    let turn = angle_between(&transform.rotation, &other.rotation);
+                    let shift = glm::length(&(transform.translation - other.translation));
+                    moved = moved.max(shift + radius * (turn * scale + scaling));
+                }
+            }
+            let mut placed = Aabb::empty();
+            for corner in 0..8 {
+                let pick = |axis: usize| {
+                    if corner & (1 << axis) == 0 {
+                        bounds.min[axis]
+                    } else {
+                        bounds.max[axis]
+                    }
+                };
+                placed = placed.grow(&transform.point(&vec3(pick(0), pick(1), pick(2))));
+            }
+            // any point between two steps is within half the distance moved of one of them
+            let pad = vec3(moved, moved, moved) * 0.5;
+            result = result.union(&Aabb::new(placed.min - pad, placed.max + pad));
+        }
+        result
+    }
+}
+
+/// Wraps an object to make it follow a motion path.
+pub struct Animated {
+    object: Box<dyn SceneObject>,
+    motion: Motion,
+}
+
+impl Animated {
+    /// Panics if the motion has no keyframes, or if they are not sorted by time.
+    pub fn new(object: Box<dyn SceneObject>, motion: Motion) -> Animated {
+        if let Motion::Keyframes(keyframes) = &motion {
+            assert!(!keyframes.is_empty(), "motion needs at least one keyframe");
+            assert!(
+                keyframes.windows(2).all(|k| k[0].time < k[1].time),
+                "keyframes must be sorted by time"
+            );
+        }
+        Animated { object, motion }
+    }
+}
+
+// converts the density of a light sample from solid angle as seen from the local origin to
+// solid angle as seen from the world origin, going through the density per unit area. rigid
+// motions and uniform scaling keep solid angles the same, but stretching does not.
+fn pdf_to_world(
+    pdf: f32,
+    transform: &Transform,
+    local: (&Vec3, &Vec3, &Vec3),
+    world: (&Vec3, &Vec3, &Vec3),
+) -> f32 {
+    let (local_origin, local_point, local_normal) = local;
+    let (world_origin, world_point, world_normal) = world;
+    let to_point = local_point - local_origin;
+    let distance2 = glm::length2(&to_point);
+    let cos = dot(local_normal, &to_point).abs() / distance2.sqrt();
+    let pdf_area = pdf * cos / distance2 / transform.area_scale(local_normal);
+    area_to_solid_angle(pdf_area, world_origin, world_point, world_normal)
+}
+
+impl SceneObject for Animated {
+    fn ray_hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        // instead of moving the object, move the ray the other way. the direction is not
+        // normalized afterwards, so distances along the ray stay the same.
+        let transform = self.motion.transform_at(ray.time);
+        let local = Ray::new(
+            transform.inverse_point(&ray.origin),
+            transform.inverse_vector(&ray.direction),
+            ray.time,
+        );
+        let hit = self.object.ray_hit(&local, t_min, t_max)?;
+        let record = HitRecord {
+            point: transform.point(&hit.point),
+            normal: transform.normal(&hit.normal),
+            geometric_normal: transform.normal(&hit.geometric_normal),
+            object: self,
+            ..hit
+        };
+        Some(record.with_tangents(
+            &transform.vector(&hit.tangent),
+            &transform.vector(&hit.bitangent),
+        ))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object
+            .bounding_box()
+            .map(|bounds| self.motion.bounds(&bounds))
+    }
+
+    fn get_material(&self) -> &dyn Material {
+        self.object.get_material()
+    }
+
+    fn sample(&self, origin: &Vec3, u: &Vec2, time: f32) -> Option<SurfaceSample> {
+        let transform = self.motion.transform_at(time);
+        let local_origin = transform.inverse_point(origin);
+        let sample = self.object.sample(&local_origin, u, time)?;
+        let point = transform.point(&sample.point);
+        let normal = transform.normal(&sample.normal);
+        let pdf = pdf_to_world(
+            sample.pdf,
+            &transform,
+            (&local_origin, &sample.point, &sample.normal),
+            (origin, &point, &normal),
+        );
+        Some(SurfaceSample {
+            point,
+            normal,
+            pdf,
+            ..sample
+        })
+    }
+
+    fn light_pdf(&self, origin: &Vec3, hit: &HitRecord, time: f32) -> f32 {
+        let transform = self.motion.transform_at(time);
+        let local_origin = transform.inverse_point(origin);
+        let local = HitRecord {
+            point: transform.inverse_point(&hit.point),
+            normal: transform.inverse_normal(&hit.normal),
+            geometric_normal: transform.inverse_normal(&hit.geometric_normal),
+            ..*hit
+        };
+        let pdf = self.object.light_pdf(&local_origin, &local, time);
+        pdf_to_world(
+            pdf,
+            &transform,
+            (&local_origin, &local.point, &local.geometric_normal),
+            (origin, &hit.point, &hit.geometric_normal),
+        )
+    }
+}
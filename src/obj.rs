@@ -0,0 +1,667 @@
+//! Loader for wavefront .obj files and the .mtl material libraries they reference.
+
+use crate::mesh::TriangleMesh;
+use crate::{
+    Dielectric, Diffuse, Emissive, ImageTexture, Material, Metal, NormalMap, NormalMapped, Texture,
+};
+use glm::{vec2, vec3, Vec2, Vec3};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::SplitWhitespace;
+
+/// Error reading an .obj or .mtl file, with the line it occurred on for parse errors, or
+/// loading a texture they reference.
+#[derive(Debug)]
+pub enum ObjError {
+    /// A file could not be read.
+    Io {
+        /// The file.
+        path: PathBuf,
+        /// Why it could not be read.
+        error: io::Error,
+    },
+    /// A texture could not be loaded.
+    Texture {
+        /// The image file of the texture.
+        path: PathBuf,
+        /// Why it could not be loaded.
+        error: image::ImageError,
+    },
+    /// A line of a file is not valid.
+    Parse {
+        /// The file.
+        path: PathBuf,
+        /// Number of the line, starting at 1.
+        line: usize,
+        /// What is wrong with the line.
+        message: String,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            ObjError::Texture { path, error } => write!(f, "{}: {}", path.display(), error),
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io { error, .. } => Some(error),
+            ObjError::Texture { error, .. } => Some(error),
+            ObjError::Parse { .. } => None,
+        }
+    }
+}
+
+/// Material as described in a .mtl file, before it gets mapped onto one of our materials.
+#[derive(Clone, Debug)]
+pub struct ObjMaterial {
+    /// Name that `usemtl` refers to the material by.
+    pub name: String,
+    /// Diffuse color.
+    pub kd: Vec3,
+    /// Specular color.
+    pub ks: Vec3,
+    /// Emissive color.
+    pub ke: Vec3,
+    /// Specular exponent.
+    pub ns: f32,
+    /// Index of refraction, if given.
+    pub ni: Option<f32>,
+    /// Opacity.
+    pub d: f32,
+    /// Illumination model.
+    pub illum: u32,
+    /// Diffuse texture, resolved relative to the .mtl file.
+    pub map_kd: Option<PathBuf>,
+    /// Bump map, resolved relative to the .mtl file.
+    pub map_bump: Option<PathBuf>,
+    /// Multiplier for the heights of the bump map.
+    pub bump_multiplier: f32,
+    /// Tangent space normal map, resolved relative to the .mtl file.
+    pub norm: Option<PathBuf>,
+}
+
+impl ObjMaterial {
+    fn new(name: &str) -> ObjMaterial {
+        ObjMaterial {
+            name: name.to_string(),
+            kd: vec3(0.8, 0.8, 0.8),
+            ks: vec3(0.0, 0.0, 0.0),
+            ke: vec3(0.0, 0.0, 0.0),
+            ns: 0.0,
+            ni: None,
+            d: 1.0,
+            illum: 2,
+            map_kd: None,
+            map_bump: None,
+            bump_multiplier: 1.0,
+            norm: None,
+        }
+    }
+
+    /// Pick the material that best matches this description. Emissive surfaces become lights,
+    /// transparent surfaces become glass, with the index of refraction of common glass unless
+    /// given, and surfaces that are mostly specular become metal, with the specular exponent
+    /// determining how rough they are. Anything else is diffuse, colored by the diffuse texture
+    /// if there is one. Surfaces that reflect light get their normal or bump map, preferring
+    /// the normal map if there are both. Fails if any of the textures can not be loaded.
+    pub fn to_material(&self) -> Result<Box<dyn Material>, ObjError> {
+        let material: Box<dyn Material> = if glm::comp_max(&self.ke) > 0.0 {
+            Box::new(Emissive {
+                emission: Box::new(self.ke),
+            })
+        } else if self.d < 1.0 || self.illum == 4 || self.illum == 6 || self.illum == 7 {
+            Box::new(Dielectric {
+                ior: self.ni.unwrap_or(1.5),
+                roughness: Box::new(0.0),
+            })
+        } else if glm::comp_max(&self.ks) > glm::comp_max(&self.kd) {
+            Box::new(Metal {
+                albedo: Box::new(self.ks),
+                scattering: Box::new((2.0 / (self.ns + 2.0)).sqrt()),
+            })
+        } else {
+            // exporters tend to write some default Kd along with the texture, so the texture
+            // replaces Kd rather than getting multiplied by it.
+            let albedo: Box<dyn Texture> = match &self.map_kd {
+                Some(path) => Box::new(load_texture(path)?),
+                None => Box::new(self.kd),
+            };
+            Box::new(Diffuse { albedo })
+        };
+        if material.is_emissive() {
+            return Ok(material);
+        }
+        // normal and bump maps hold data rather than colors, so they are not gamma encoded.
+        // .mtl files do not say how high bumps are, so take the heights to span a hundredth of
+        // the texture, scaled by the bump multiplier.
+        let map = if let Some(path) = &self.norm {
+            NormalMap::Tangent(Box::new(load_texture(path)?.with_gamma(1.0)))
+        } else if let Some(path) = &self.map_bump {
+            NormalMap::Bump {
+                height: Box::new(load_texture(path)?.with_gamma(1.0)),
+                strength: 0.01 * self.bump_multiplier,
+            }
+        } else {
+            return Ok(material);
+        };
+        Ok(Box::new(NormalMapped { material, map }))
+    }
+}
+
+fn load_texture(path: &Path) -> Result<ImageTexture, ObjError> {
+    ImageTexture::load(path).map_err(|error| ObjError::Texture {
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
+// the faces using one material, with their own vertex list. obj files index positions, normals
+// and texture coordinates separately, so each unique combination becomes a mesh vertex.
+#[derive(Default)]
+struct MeshBuilder {
+    vertex_map: HashMap<(usize, Option<usize>, Option<usize>), usize>,
+    positions: Vec<Vec3>,
+    normals: Vec<Option<Vec3>>,
+    uvs: Vec<Option<Vec2>>,
+    indices: Vec<[usize; 3]>,
+}
+
+impl MeshBuilder {
+    fn vertex(&mut self, obj: &ObjData, key: (usize, Option<usize>, Option<usize>)) -> usize {
+        if let Some(&i) = self.vertex_map.get(&key) {
+            return i;
+        }
+        let (v, vt, vn) = key;
+        let i = self.positions.len();
+        self.positions.push(obj.positions[v]);
+        self.uvs.push(vt.map(|vt| obj.uvs[vt]));
+        self.normals.push(vn.map(|vn| obj.normals[vn]));
+        self.vertex_map.insert(key, i);
+        i
+    }
+
+    // normals and uvs can only be used if every vertex has them.
+    fn build(self, material: Box<dyn Material>) -> TriangleMesh {
+        let normals = self.normals.into_iter().collect::<Option<Vec<_>>>();
+        let uvs = self.uvs.into_iter().collect::<Option<Vec<_>>>();
+        TriangleMesh::new(
+            self.positions,
+            normals.unwrap_or_default(),
+            uvs.unwrap_or_default(),
+            self.indices,
+            material,
+        )
+    }
+}
+
+#[derive(Default)]
+struct ObjData {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<Vec2>,
+}
+
+// reads the statements of a single line, keeping track of where we are for error messages.
+struct LineParser<'a> {
+    path: &'a Path,
+    line: usize,
+    tokens: SplitWhitespace<'a>,
+}
+
+impl<'a> LineParser<'a> {
+    fn error(&self, message: String) -> ObjError {
+        ObjError::Parse {
+            path: self.path.to_path_buf(),
+            line: self.line,
+            message,
+        }
+    }
+
+    fn float(&mut self, what: &str) -> Result<f32, ObjError> {
+        match self.tokens.next() {
+            Some(t) => t
+                .parse()
+                .map_err(|_| self.error(format!("invalid number '{}' in {}", t, what))),
+            None => Err(self.error(format!("missing number in {}", what))),
+        }
+    }
+
+    fn integer(&mut self, what: &str) -> Result<u32, ObjError> {
+        match self.tokens.next() {
+            Some(t) => t
+                .parse()
+                .map_err(|_| self.error(format!("invalid integer '{}' in {}", t, what))),
+            None => Err(self.error(format!("missing integer in {}", what))),
+        }
+    }
+
+    fn vec3(&mut self, what: &str) -> Result<Vec3, ObjError> {
+        Ok(vec3(
+            self.float(what)?,
+            self.float(what)?,
+            self.float(what)?,
+        ))
+    }
+
+    // the rest of the line, for names and file names which may contain spaces
+    fn rest(&mut self, what: &str) -> Result<String, ObjError> {
+        let rest: Vec<&str> = self.tokens.by_ref().collect();
+        if rest.is_empty() {
+            Err(self.error(format!("missing {}", what)))
+        } else {
+            Ok(rest.join(" "))
+        }
+    }
+}
+
+// the line without its comment, which starts at a '#' at the start of a token. a '#' inside a
+// token, like in a file name, is kept.
+fn strip_comment(line: &str) -> &str {
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        if c == '#' && previous.is_whitespace() {
+            return &line[..i];
+        }
+        previous = c;
+    }
+    line
+}
+
+fn read_file(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|error| ObjError::Io {
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
+// resolves a 1-based (or negative, relative to the end) obj index into a 0-based index.
+fn resolve_index(
+    parser: &LineParser,
+    token: &str,
+    count: usize,
+    what: &str,
+) -> Result<usize, ObjError> {
+    let index: i64 = token
+        .parse()
+        .map_err(|_| parser.error(format!("invalid {} index '{}'", what, token)))?;
+    let resolved = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1
+    };
+    if resolved < 0 || resolved >= count as i64 {
+        Err(parser.error(format!(
+            "{} index {} out of range, only {} defined so far",
+            what, index, count
+        )))
+    } else {
+        Ok(resolved as usize)
+    }
+}
+
+// parses a face vertex in any of the forms v, v/vt, v//vn or v/vt/vn
+fn parse_face_vertex(
+    parser: &LineParser,
+    token: &str,
+    obj: &ObjData,
+) -> Result<(usize, Option<usize>, Option<usize>), ObjError> {
+    let mut parts = token.split('/');
+    let v = match parts.next() {
+        Some(v) if !v.is_empty() => resolve_index(parser, v, obj.positions.len(), "vertex")?,
+        _ => return Err(parser.error(format!("invalid face vertex '{}'", token))),
+    };
+    let vt = match parts.next() {
+        Some(vt) if !vt.is_empty() => Some(resolve_index(
+            parser,
+            vt,
+            obj.uvs.len(),
+            "texture coordinate",
+        )?),
+        _ => None,
+    };
+    let vn = match parts.next() {
+        Some(vn) if !vn.is_empty() => Some(resolve_index(parser, vn, obj.normals.len(), "normal")?),
+        _ => None,
+    };
+    if parts.next().is_some() {
+        return Err(parser.error(format!("invalid face vertex '{}'", token)));
+    }
+    Ok((v, vt, vn))
+}
+
+/// Loads the materials from a .mtl file.
+pub fn load_mtl(path: &Path) -> Result<HashMap<String, ObjMaterial>, ObjError> {
+    parse_mtl(&read_file(path)?, path)
+}
+
+// parses the text of the .mtl file at the path, which errors and texture paths refer to
+fn parse_mtl(text: &str, path: &Path) -> Result<HashMap<String, ObjMaterial>, ObjError> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut materials = HashMap::new();
+    let mut current: Option<ObjMaterial> = None;
+
+    for (i, line) in text.lines().enumerate() {
+        let line = strip_comment(line);
+        let mut parser = LineParser {
+            path,
+            line: i + 1,
+            tokens: line.split_whitespace(),
+        };
+        let keyword = match parser.tokens.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        if keyword == "newmtl" {
+            let name = parser.rest("material name")?;
+            if let Some(m) = current.take() {
+                materials.insert(m.name.clone(), m);
+            }
+            current = Some(ObjMaterial::new(&name));
+            continue;
+        }
+        let mat = match current.as_mut() {
+            Some(m) => m,
+            None => {
+                return Err(parser.error(format!("'{}' before the first newmtl", keyword)));
+            }
+        };
+        match keyword {
+            "Kd" => mat.kd = parser.vec3("Kd")?,
+            "Ks" => mat.ks = parser.vec3("Ks")?,
+            "Ke" => mat.ke = parser.vec3("Ke")?,
+            "Ns" => mat.ns = parser.float("Ns")?,
+            "Ni" => mat.ni = Some(parser.float("Ni")?),
+            "d" => mat.d = parser.float("d")?,
+            "Tr" => mat.d = 1.0 - parser.float("Tr")?,
+            "illum" => mat.illum = parser.integer("illum")?,
+            "map_Kd" => {
+                // texture options come before the file name. we do not support any of them,
+                // so just take the file name at the end.
+                let rest = parser.rest("texture file name")?;
+                let file = rest.rsplit(' ').next().unwrap_or(&rest);
+                mat.map_kd = Some(dir.join(file));
+            }
+            "map_Bump" | "bump" => {
+                // -bm scales the heights, which is the only texture option we support here
+                let rest = parser.rest("texture file name")?;
+                let tokens: Vec<&str> = rest.split(' ').collect();
+                if let Some(i) = tokens.iter().position(|&t| t == "-bm") {
+                    let value = tokens.get(i + 1).unwrap_or(&"");
+                    mat.bump_multiplier = value.parse().map_err(|_| {
+                        parser.error(format!("invalid number '{}' in bump multiplier", value))
+                    })?;
+                }
+                mat.map_bump = Some(dir.join(tokens[tokens.len() - 1]));
+            }
+            "norm" => {
+                let rest = parser.rest("texture file name")?;
+                let file = rest.rsplit(' ').next().unwrap_or(&rest);
+                mat.norm = Some(dir.join(file));
+            }
+            // ignore anything we have no use for
+            _ => {}
+        }
+    }
+    if let Some(m) = current.take() {
+        materials.insert(m.name.clone(), m);
+    }
+    Ok(materials)
+}
+
+/// Loads an .obj file, returning one mesh per material used by its faces. Polygons with more
+/// than three vertices are split up into triangles.
+pub fn load_obj(path: &Path) -> Result<Vec<TriangleMesh>, ObjError> {
+    parse_obj(&read_file(path)?, path)
+}
+
+// parses the text of the .obj file at the path, which errors and material libraries refer to
+fn parse_obj(text: &str, path: &Path) -> Result<Vec<TriangleMesh>, ObjError> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut obj = ObjData::default();
+    let mut materials: HashMap<String, ObjMaterial> = HashMap::new();
+    // faces are grouped per material, in order of first use
+    let mut groups: Vec<(Option<String>, MeshBuilder)> = Vec::new();
+    let mut current_group: Option<usize> = None;
+    let mut current_material: Option<String> = None;
+
+    for (i, line) in text.lines().enumerate() {
+        let line = strip_comment(line);
+        let mut parser = LineParser {
+            path,
+            line: i + 1,
+            tokens: line.split_whitespace(),
+        };
+        let keyword = match parser.tokens.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        match keyword {
+            "v" => {
+                let v = parser.vec3("vertex position")?;
+                obj.positions.push(v);
+            }
+            "vn" => {
+                let n = parser.vec3("vertex normal")?;
+                obj.normals.push(n);
+            }
+            "vt" => {
+                let u = parser.float("texture coordinate")?;
+                // v is optional for 1D textures
+                let v = match parser.tokens.next() {
+                    Some(t) => t.parse().map_err(|_| {
+                        parser.error(format!("invalid number '{}' in texture coordinate", t))
+                    })?,
+                    None => 0.0,
+                };
+                obj.uvs.push(vec2(u, v));
+            }
+            "f" => {
+                let tokens: Vec<&str> = parser.tokens.by_ref().collect();
+                if tokens.len() < 3 {
+                    return Err(parser.error(format!(
+                        "face needs at least 3 vertices, found {}",
+                        tokens.len()
+                    )));
+                }
+                let mut keys = Vec::with_capacity(tokens.len());
+                for t in tokens {
+                    keys.push(parse_face_vertex(&parser, t, &obj)?);
+                }
+                let group = match current_group {
+                    Some(g) => g,
+                    None => {
+                        groups.push((current_material.clone(), MeshBuilder::default()));
+                        groups.len() - 1
+                    }
+                };
+                current_group = Some(group);
+                let builder = &mut groups[group].1;
+                let indices: Vec<usize> =
+                    keys.into_iter().map(|k| builder.vertex(&obj, k)).collect();
+                // triangulate as a fan, which works for the convex polygons found in practice
+                for k in 1..indices.len() - 1 {
+                    builder
+                        .indices
+                        .push([indices[0], indices[k], indices[k + 1]]);
+                }
+            }
+            "mtllib" => {
+                // any number of libraries, separated by whitespace
+                let files: Vec<&str> = parser.tokens.by_ref().collect();
+                if files.is_empty() {
+                    return Err(parser.error("missing material library file name".to_string()));
+                }
+                for file in files {
+                    materials.extend(load_mtl(&dir.join(file))?);
+                }
+            }
+            "usemtl" => {
+                let name = parser.rest("material name")?;
+                if !materials.contains_key(&name) {
+                    return Err(parser.error(format!("unknown material '{}'", name)));
+                }
+                current_group = groups.iter().position(|(m, _)| m.as_ref() == Some(&name));
+                current_material = Some(name);
+            }
+            // groups, objects and smoothing groups do not affect how we render the mesh, and
+            // points, lines and curves can not be rendered at all.
+            _ => {}
+        }
+    }
+
+    let mut meshes = Vec::new();
+    for (material, builder) in groups {
+        let material = match material {
+            Some(name) => materials[&name].to_material()?,
+            None => Box::new(Diffuse::default()),
+        };
+        meshes.push(builder.build(material));
+    }
+    Ok(meshes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn obj_error(text: &str) -> String {
+        match parse_obj(text, Path::new("test.obj")) {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    fn mtl_error(text: &str) -> String {
+        match parse_mtl(text, Path::new("test.mtl")) {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_faces() {
+        let text = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\nf -4 -2 -1\n";
+        let meshes = parse_obj(text, Path::new("test.obj")).unwrap();
+        assert_eq!(meshes.len(), 1);
+    }
+
+    #[test]
+    fn reports_vertex_index_out_of_range() {
+        assert_eq!(
+            obj_error("v 0 0 0\nv 1 0 0\nf 1 2 3\n"),
+            "test.obj:3: vertex index 3 out of range, only 2 defined so far"
+        );
+        assert_eq!(
+            obj_error("v 0 0 0\nf 1 -2 1\n"),
+            "test.obj:2: vertex index -2 out of range, only 1 defined so far"
+        );
+    }
+
+    #[test]
+    fn reports_invalid_numbers() {
+        assert_eq!(
+            obj_error("v 0 0 0\nv 1 x 0\n"),
+            "test.obj:2: invalid number 'x' in vertex position"
+        );
+        assert_eq!(
+            obj_error("\n\nvn 0 1\n"),
+            "test.obj:3: missing number in vertex normal"
+        );
+        assert_eq!(
+            mtl_error("newmtl a\nKd 1 0.5 oops\n"),
+            "test.mtl:2: invalid number 'oops' in Kd"
+        );
+    }
+
+    #[test]
+    fn reports_invalid_faces() {
+        assert_eq!(
+            obj_error("v 0 0 0\nf 1 1\n"),
+            "test.obj:2: face needs at least 3 vertices, found 2"
+        );
+        assert_eq!(
+            obj_error("v 0 0 0\nf 1/1 1 1\n"),
+            "test.obj:2: texture coordinate index 1 out of range, only 0 defined so far"
+        );
+        assert_eq!(
+            obj_error("v 0 0 0\nusemtl missing\n"),
+            "test.obj:2: unknown material 'missing'"
+        );
+    }
+
+    #[test]
+    fn reports_statements_before_newmtl() {
+        assert_eq!(
+            mtl_error("# comment\nKd 1 1 1\nnewmtl a\n"),
+            "test.mtl:2: 'Kd' before the first newmtl"
+        );
+    }
+
+    #[test]
+    fn ior_is_only_set_when_given() {
+        let materials = parse_mtl("newmtl glass\nd 0.5\n", Path::new("test.mtl")).unwrap();
+        assert_eq!(materials["glass"].ni, None);
+        let materials = parse_mtl("newmtl glass\nd 0.5\nNi 1.33\n", Path::new("test.mtl")).unwrap();
+        assert_eq!(materials["glass"].ni, Some(1.33));
+    }
+
+    #[test]
+    fn parses_illum_as_an_integer() {
+        let materials = parse_mtl(
+            "newmtl glass\nillum 7 # refraction\n",
+            Path::new("test.mtl"),
+        );
+        assert_eq!(materials.unwrap()["glass"].illum, 7);
+        assert_eq!(
+            mtl_error("newmtl a\nillum 2.5\n"),
+            "test.mtl:2: invalid integer '2.5' in illum"
+        );
+        assert_eq!(
+            mtl_error("newmtl a\nillum\n"),
+            "test.mtl:2: missing integer in illum"
+        );
+    }
+
+    #[test]
+    fn strips_comments_only_at_the_start_of_a_token() {
+        assert_eq!(strip_comment("# comment"), "");
+        assert_eq!(strip_comment("v 1 2 3 # comment"), "v 1 2 3 ");
+        assert_eq!(strip_comment("v 1 2 3\t#comment"), "v 1 2 3\t");
+        assert_eq!(strip_comment("mtllib scene#2.mtl"), "mtllib scene#2.mtl");
+        let materials = parse_mtl("newmtl a#1 # first\nKd 1 0 0\n", Path::new("test.mtl"));
+        assert!(materials.unwrap().contains_key("a#1"));
+    }
+
+    #[test]
+    fn loads_every_material_library() {
+        let dir = std::env::temp_dir().join(format!("raytracer-obj-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.mtl"), "newmtl red\nKd 1 0 0\n").unwrap();
+        fs::write(dir.join("b#2.mtl"), "newmtl green\nKd 0 1 0\n").unwrap();
+        let text = "mtllib a.mtl b#2.mtl # both\nv 0 0 0\nv 1 0 0\nv 1 1 0\n\
+                    usemtl red\nf 1 2 3\nusemtl green\nf 1 2 3\n";
+        let meshes = parse_obj(text, &dir.join("test.obj"));
+        let missing = parse_obj("mtllib a.mtl c.mtl\n", &dir.join("test.obj")).is_err();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(meshes.unwrap().len(), 2);
+        assert!(missing, "a missing library must be reported");
+        assert_eq!(
+            obj_error("mtllib # none\n"),
+            "test.obj:1: missing material library file name"
+        );
+    }
+}
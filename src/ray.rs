@@ -0,0 +1,31 @@
+//! Rays, the half-lines along which light travels through the scene.
+
+use glm::Vec3;
+
+/// Half-line starting at `origin`, going in the direction of `direction`.
+/// The direction does not need to be normalized.
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    /// Point the ray starts at.
+    pub origin: Vec3,
+    /// Direction the ray goes in.
+    pub direction: Vec3,
+    /// Moment at which the ray travels through the scene, for objects that move.
+    pub time: f32,
+}
+
+impl Ray {
+    /// Ray from `origin` going in `direction`, at moment `time`.
+    pub fn new(origin: Vec3, direction: Vec3, time: f32) -> Ray {
+        Ray {
+            origin,
+            direction,
+            time,
+        }
+    }
+
+    /// Point at distance `t` along the ray, measured in multiples of the direction vector.
+    pub fn point_at(&self, t: f32) -> Vec3 {
+        self.origin + self.direction * t
+    }
+}
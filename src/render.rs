@@ -0,0 +1,448 @@
+//! Renders images of scenes by path tracing: following rays from the camera as they bounce
+//! around the scene, and adding up the light they pick up along the way.
+
+use crate::camera::{Camera, CameraSample, Rig, StereoLayout};
+use crate::light;
+use crate::material::luminance;
+use crate::ray::Ray;
+use crate::sampler::{Sampler, SamplerKind};
+use crate::scene::Scene;
+use crate::texture::ColorRamp;
+use glm::{vec2, vec3, Vec3};
+use image::{Rgb, RgbImage};
+use rayon::prelude::*;
+use std::path::PathBuf;
+
+/// Settings controlling the quality and size of a render.
+pub struct RenderSettings {
+    /// Width of the image in pixels.
+    pub width: u32,
+    /// Height of the image in pixels.
+    pub height: u32,
+    /// Number of samples per pixel. With adaptive sampling, this is the most a pixel gets.
+    pub samples: u32,
+    /// Maximum number of bounces per path.
+    pub max_depth: u32,
+    /// Kind of sampler to take the samples with.
+    pub sampler: SamplerKind,
+    /// Seed that all random numbers of the render derive from. Renders with the same settings
+    /// come out identical, however many threads render them.
+    pub seed: u64,
+    /// Adaptive sampling, which stops taking samples for pixels that are already good enough.
+    /// Without it, every pixel gets the same number of samples.
+    pub adaptive: Option<AdaptiveSampling>,
+    /// Where front ends should write the image to. Not used by the renderer itself.
+    pub output: PathBuf,
+    /// Where front ends should write the heatmap of the number of samples taken for each pixel
+    /// to, if anywhere. Not used by the renderer itself.
+    pub heatmap: Option<PathBuf>,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            width: 2048,
+            height: 1024,
+            samples: 64,
+            max_depth: 64,
+            sampler: SamplerKind::Sobol,
+            seed: 0,
+            adaptive: None,
+            output: PathBuf::from("target/out.png"),
+            heatmap: None,
+        }
+    }
+}
+
+/// Settings for adaptive sampling. Pixels take samples in rounds, each doubling the number of
+/// samples taken so far, until the estimated error of their brightness drops below the
+/// threshold or they reach the number of samples of the render settings.
+///
+/// The error is the standard error of the mean brightness, relative to that brightness. Flat
+/// areas like the sky get done in the first round, while noisy areas like soft shadows and
+/// reflections of lights keep going.
+#[derive(Clone, Copy, Debug)]
+pub struct AdaptiveSampling {
+    /// Number of samples every pixel takes before its error is first estimated. Too few
+    /// samples may miss rare but bright paths, and make the pixel look done too soon.
+    pub min_samples: u32,
+    /// Relative error at which a pixel stops taking samples, like 0.01 for 1%.
+    pub threshold: f32,
+}
+
+impl Default for AdaptiveSampling {
+    fn default() -> Self {
+        AdaptiveSampling {
+            min_samples: 16,
+            threshold: 0.02,
+        }
+    }
+}
+
+/// Rendered image, holding the linear radiance arriving at each pixel.
+pub struct Framebuffer {
+    /// Width of the image in pixels.
+    pub width: u32,
+    /// Height of the image in pixels.
+    pub height: u32,
+    /// Pixels in row-major order, starting at the top left.
+    pub pixels: Vec<Vec3>,
+    /// Number of samples taken for each pixel, in the same order.
+    pub sample_counts: Vec<u32>,
+}
+
+impl Framebuffer {
+    /// Black image of the given size, without any samples.
+    pub fn new(width: u32, height: u32) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![vec3(0.0, 0.0, 0.0); (width * height) as usize],
+            sample_counts: vec![0; (width * height) as usize],
+        }
+    }
+
+    /// Pixel in column `x` and row `y`, counting from the top left.
+    pub fn get(&self, x: u32, y: u32) -> Vec3 {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    /// Combines two images of the same width into one, with `self` on top of `bottom`.
+    pub fn stack(&self, bottom: &Framebuffer) -> Framebuffer {
+        assert_eq!(
+            self.width, bottom.width,
+            "stacked images must be equally wide"
+        );
+        let mut pixels = self.pixels.clone();
+        pixels.extend_from_slice(&bottom.pixels);
+        let mut sample_counts = self.sample_counts.clone();
+        sample_counts.extend_from_slice(&bottom.sample_counts);
+        Framebuffer {
+            width: self.width,
+            height: self.height + bottom.height,
+            pixels,
+            sample_counts,
+        }
+    }
+
+    /// Converts to an 8-bit image, gamma encoding the pixel values and clamping them to the
+    /// displayable range.
+    pub fn to_image(&self) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            vec3_to_rgb(&encode_gamma(&self.get(x, y), 2.2))
+        })
+    }
+
+    /// Heatmap of the number of samples taken for each pixel, going from black for none over
+    /// blue and red to yellow for `max_samples` or more.
+    pub fn heatmap(&self, max_samples: u32) -> RgbImage {
+        let ramp = ColorRamp::new(vec![
+            (0.0, vec3(0.0, 0.0, 0.0)),
+            (0.25, vec3(0.1, 0.1, 0.7)),
+            (0.6, vec3(0.8, 0.1, 0.2)),
+            (1.0, vec3(1.0, 0.9, 0.2)),
+        ]);
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            let count = self.sample_counts[(y * self.width + x) as usize];
+            vec3_to_rgb(&ramp.color_at(count as f32 / max_samples.max(1) as f32))
+        })
+    }
+}
+
+/// Renders the scene as seen by the camera.
+pub fn render(camera: &dyn Camera, scene: &Scene, settings: &RenderSettings) -> Framebuffer {
+    let width = settings.width;
+    let height = settings.height;
+    let aspect_ratio = (width as f32) / (height as f32);
+    let num_samples = settings.samples;
+
+    // for each pixel, shoot rays to determine color.
+    // use rayon's parallel iterator to divide work over all cpu cores.
+    let (pixels, sample_counts) = (0..width * height)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            // do a number of ray samples. the samplers derive their numbers from the pixel and
+            // sample index, so they do not depend on which thread renders the pixel.
+            let mut estimate = PixelEstimate::new();
+            // number of samples to take before deciding whether the pixel needs more
+            let mut round_end = match &settings.adaptive {
+                Some(adaptive) => adaptive.min_samples.clamp(1, num_samples.max(1)),
+                None => num_samples,
+            };
+            loop {
+                // each round gets a sampler of its own, so its samples are spread well over
+                // the pixel by themselves, wherever the pixel stops. later rounds put their
+                // start into the high bits of the seed, so they do not repeat earlier samples.
+                let start = estimate.count;
+                let seed = settings.seed ^ (u64::from(start) << 32);
+                let mut sampler = settings.sampler.build(round_end - start, seed);
+                for s in start..round_end {
+                    sampler.start_pixel_sample([x, y], s - start);
+                    let jitter = sampler.get_2d();
+                    let u: f32 = (x as f32 + jitter.x) / width as f32;
+                    let v: f32 = (y as f32 + jitter.y) / height as f32;
+                    let sample = CameraSample {
+                        film: vec2(u, v),
+                        lens: sampler.get_2d(),
+                        time: sampler.get_1d(),
+                    };
+                    // parts of the image that the camera does not cover stay black
+                    let color = match camera.generate_ray(&sample, aspect_ratio) {
+                        Some(ray) => trace_ray(&ray, scene, settings.max_depth, sampler.as_mut()),
+                        None => vec3(0.0, 0.0, 0.0),
+                    };
+                    estimate.add(&color);
+                }
+                match &settings.adaptive {
+                    Some(adaptive)
+                        if round_end < num_samples
+                            && estimate.relative_error() > adaptive.threshold =>
+                    {
+                        round_end = (2 * round_end).min(num_samples);
+                    }
+                    _ => break,
+                }
+            }
+            (estimate.mean(), estimate.count)
+        })
+        .unzip();
+
+    Framebuffer {
+        width,
+        height,
+        pixels,
+        sample_counts,
+    }
+}
+
+// running estimate of a pixel's color, along with the variance of its brightness
+struct PixelEstimate {
+    count: u32,
+    sum: Vec3,
+    // mean brightness and sum of squared differences from it, updated with welford's method
+    mean_luminance: f32,
+    squared_deviations: f32,
+}
+
+impl PixelEstimate {
+    fn new() -> PixelEstimate {
+        PixelEstimate {
+            count: 0,
+            sum: vec3(0.0, 0.0, 0.0),
+            mean_luminance: 0.0,
+            squared_deviations: 0.0,
+        }
+    }
+
+    fn add(&mut self, color: &Vec3) {
+        self.count += 1;
+        self.sum += color;
+        let luminance = luminance(color);
+        let delta = luminance - self.mean_luminance;
+        self.mean_luminance += delta / self.count as f32;
+        self.squared_deviations += delta * (luminance - self.mean_luminance);
+    }
+
+    fn mean(&self) -> Vec3 {
+        self.sum / self.count.max(1) as f32
+    }
+
+    // standard error of the mean brightness, relative to it. the brightness counts as at least
+    // 0.01, so dark pixels with a tiny bit of noise do not look hopelessly inaccurate.
+    fn relative_error(&self) -> f32 {
+        if self.count < 2 {
+            return f32::INFINITY;
+        }
+        let n = self.count as f32;
+        let variance = self.squared_deviations / (n - 1.0);
+        (variance / n).sqrt() / self.mean_luminance.max(0.01)
+    }
+}
+
+/// Renders the scene with the cameras of the rig, see [`render`]. This gives a single
+/// framebuffer, except for stereo rigs that keep the eyes separate, which give the left and
+/// right eye framebuffers in that order. The render settings give the size of the image for
+/// each eye, so a framebuffer packing both eyes is twice as high.
+pub fn render_rig(rig: &Rig, scene: &Scene, settings: &RenderSettings) -> Vec<Framebuffer> {
+    match rig {
+        Rig::Mono(camera) => vec![render(camera.as_ref(), scene, settings)],
+        Rig::Stereo {
+            left,
+            right,
+            layout,
+        } => {
+            let left = render(left.as_ref(), scene, settings);
+            let right = render(right.as_ref(), scene, settings);
+            match layout {
+                StereoLayout::Separate => vec![left, right],
+                StereoLayout::TopBottom => vec![left.stack(&right)],
+            }
+        }
+    }
+}
+
+/// Renders the scene with the cameras of the rig straight into 8-bit images, see
+/// [`render_rig`].
+pub fn render_image(rig: &Rig, scene: &Scene, settings: &RenderSettings) -> Vec<RgbImage> {
+    render_rig(rig, scene, settings)
+        .iter()
+        .map(Framebuffer::to_image)
+        .collect()
+}
+
+/// Estimates the light arriving along the ray, following it for at most `max_depth` bounces.
+/// All random decisions along the path are made with numbers from the sampler.
+pub fn trace_ray(ray: &Ray, scene: &Scene, max_depth: u32, sampler: &mut dyn Sampler) -> Vec3 {
+    let mut color = vec3(0.0, 0.0, 0.0);
+    // fraction of the light arriving at the current path vertex that makes it back to the camera
+    let mut throughput = vec3(1.0, 1.0, 1.0);
+    let mut ray = *ray;
+    // pdf with which the material picked the current ray direction. None for camera rays and
+    // for delta bounces, like off mirrors and smooth glass, which light sampling can not find.
+    let mut bsdf_pdf: Option<f32> = None;
+
+    for depth in 0..=max_depth {
+        let h = match scene.hit(&ray, 0.001, f32::MAX) {
+            Some(h) => h,
+            // we did not hit anything, so add background color
+            None => {
+                color += throughput.component_mul(&scene.background.color(&ray));
+                break;
+            }
+        };
+
+        // we hit something, so take the light it emits. if the light could also have been found
+        // by direct light sampling at the previous vertex, weigh it so it is not counted twice.
+        let emitted = h.material.emitted(&ray, &h);
+        if emitted != Vec3::zeros() {
+            let weight = match bsdf_pdf {
+                Some(pdf) => {
+                    light::power_heuristic(pdf, light::light_pdf(scene, &ray.origin, &h, ray.time))
+                }
+                None => 1.0,
+            };
+            color += throughput.component_mul(&emitted) * weight;
+        }
+        if depth == max_depth {
+            break;
+        }
+
+        // light arriving directly from the lights
+        color += throughput.component_mul(&light::sample_direct_light(&ray, &h, scene, sampler));
+
+        // and do a bounce in a random direction for the indirect light
+        let sample = match h.material.sample(&ray, &h, sampler) {
+            Some(s) => s,
+            None => break,
+        };
+        bsdf_pdf = if sample.delta { None } else { Some(sample.pdf) };
+        throughput = throughput.component_mul(&sample.weight);
+        ray = Ray::new(h.point, sample.direction, ray.time);
+    }
+    color
+}
+
+fn vec3_to_rgb(v: &Vec3) -> Rgb<u8> {
+    let a = v * 255.99;
+    let b = glm::clamp(&a, 0.0, 255.0);
+    Rgb([b.x as u8, b.y as u8, b.z as u8])
+}
+
+fn encode_gamma(color: &Vec3, gamma: f32) -> Vec3 {
+    let inv = 1.0 / gamma;
+    let exp = vec3(inv, inv, inv);
+    glm::pow(color, &exp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{Perspective, View};
+    use crate::material::{Diffuse, Emissive};
+    use crate::scene::SceneObject;
+    use crate::shapes::{Plane, Sphere};
+
+    // a diffuse sphere on the ground, lit by a sphere light
+    fn test_scene() -> (Scene, Perspective) {
+        let objects: Vec<Box<dyn SceneObject>> = vec![
+            Box::new(Plane {
+                point: vec3(0.0, 0.0, 0.0),
+                normal: vec3(0.0, 1.0, 0.0),
+                material: Box::new(Diffuse::default()),
+            }),
+            Box::new(Sphere {
+                position: vec3(0.0, 1.0, 0.0),
+                radius: 1.0,
+                material: Box::new(Diffuse::default()),
+            }),
+            Box::new(Sphere {
+                position: vec3(2.0, 3.0, 1.0),
+                radius: 0.5,
+                material: Box::new(Emissive {
+                    emission: Box::new(vec3(10.0, 10.0, 10.0)),
+                }),
+            }),
+        ];
+        let view = View::look_at(
+            vec3(0.0, 2.0, 6.0),
+            vec3(0.0, 1.0, 0.0),
+            vec3(0.0, 1.0, 0.0),
+        );
+        (Scene::new(objects), Perspective::new(view, 40.0))
+    }
+
+    fn small_settings() -> RenderSettings {
+        RenderSettings {
+            width: 16,
+            height: 12,
+            samples: 8,
+            max_depth: 4,
+            ..RenderSettings::default()
+        }
+    }
+
+    #[test]
+    fn adaptive_render_without_samples_does_not_panic() {
+        let (scene, camera) = test_scene();
+        let settings = RenderSettings {
+            samples: 0,
+            adaptive: Some(AdaptiveSampling::default()),
+            ..small_settings()
+        };
+        let framebuffer = render(&camera, &scene, &settings);
+        assert_eq!(framebuffer.pixels.len(), 16 * 12);
+    }
+
+    // renders with the given number of threads, returning the bits of every pixel
+    fn render_with_threads(settings: &RenderSettings, threads: usize) -> (Vec<u32>, Vec<u32>) {
+        let (scene, camera) = test_scene();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        let framebuffer = pool.install(|| render(&camera, &scene, settings));
+        let bits = framebuffer
+            .pixels
+            .iter()
+            .flat_map(|p| p.iter().map(|c| c.to_bits()).collect::<Vec<u32>>())
+            .collect();
+        (bits, framebuffer.sample_counts)
+    }
+
+    #[test]
+    fn renders_do_not_depend_on_the_thread_count() {
+        let adaptive = RenderSettings {
+            samples: 32,
+            adaptive: Some(AdaptiveSampling {
+                min_samples: 4,
+                threshold: 0.1,
+            }),
+            ..small_settings()
+        };
+        for settings in [small_settings(), adaptive] {
+            let single = render_with_threads(&settings, 1);
+            assert_eq!(single, render_with_threads(&settings, 4));
+        }
+    }
+}
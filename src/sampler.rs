@@ -0,0 +1,563 @@
+//! Samplers generate the random numbers that rendering takes its samples with. Independent
+//! random numbers tend to clump together and leave gaps, so images converge slowly. The other
+//! samplers spread the samples of each pixel evenly over every dimension they are used in,
+//! which gets rid of noise a lot faster.
+//!
+//! Each sample of a pixel asks for its numbers one or two dimensions at a time: first the point
+//! on the film, then the lens, the time, and after that the numbers for each bounce of the path.
+//! The structured samplers are randomized per pixel and dimension by hashing, so neighbouring
+//! pixels and dimensions do not end up correlated.
+
+use glm::{vec2, Vec2};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+/// Source of the random numbers used while rendering, each uniformly distributed in [0, 1).
+pub trait Sampler {
+    /// Starts sample `index` of the pixel at column and row `pixel`, from its first dimension.
+    fn start_pixel_sample(&mut self, pixel: [u32; 2], index: u32);
+
+    /// Next dimension of the current sample.
+    fn get_1d(&mut self) -> f32;
+
+    /// Next two dimensions of the current sample, as a point in the unit square.
+    fn get_2d(&mut self) -> Vec2;
+}
+
+/// The kinds of samplers to render with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SamplerKind {
+    /// Independent uniform random numbers.
+    Independent,
+    /// Jittered stratification, with the strata of each dimension in a random order.
+    Stratified,
+    /// Owen scrambled Halton sequence, using a prime base per dimension.
+    Halton,
+    /// Owen scrambled Sobol sequence, padded to higher dimensions by randomly shuffling the
+    /// samples of its first two dimensions. Works best with a power of two samples per pixel.
+    Sobol,
+}
+
+impl SamplerKind {
+    /// Sampler of this kind for taking `samples` samples per pixel, randomized by `seed`.
+    pub fn build(self, samples: u32, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples, seed)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(samples, seed)),
+        }
+    }
+}
+
+/// Independent uniform random numbers, from a generator seeded for each pixel sample.
+pub struct IndependentSampler {
+    seed: u64,
+    rng: SmallRng,
+}
+
+impl IndependentSampler {
+    /// Sampler whose numbers are randomized by `seed`.
+    pub fn new(seed: u64) -> IndependentSampler {
+        IndependentSampler {
+            seed,
+            rng: SmallRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, pixel: [u32; 2], index: u32) {
+        let seed = hash(&[pixel[0] as u64, pixel[1] as u64, index as u64, self.seed]);
+        self.rng = SmallRng::seed_from_u64(seed);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.rng.gen()
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        vec2(self.rng.gen(), self.rng.gen())
+    }
+}
+
+// position of the current sample within the sequence of samples for all pixels
+#[derive(Clone, Copy, Default)]
+struct SampleState {
+    pixel: [u32; 2],
+    index: u32,
+    dimension: u32,
+}
+
+impl SampleState {
+    fn start(&mut self, pixel: [u32; 2], index: u32) {
+        *self = SampleState {
+            pixel,
+            index,
+            dimension: 0,
+        };
+    }
+
+    // hash identifying the current dimension of the pixel, the same for all its samples
+    fn dimension_hash(&self, seed: u64) -> u64 {
+        hash(&[
+            self.pixel[0] as u64,
+            self.pixel[1] as u64,
+            self.dimension as u64,
+            seed,
+        ])
+    }
+
+    // independent random number for the current dimension of the current sample
+    fn random(&self, seed: u64, salt: u64) -> f32 {
+        let h = hash(&[self.dimension_hash(seed), self.index as u64, salt]);
+        to_unit_float((h >> 32) as u32)
+    }
+}
+
+/// Divides each dimension into as many strata as there are samples per pixel, and places each
+/// sample at a random point in its own stratum. Pairs of dimensions are stratified together,
+/// in a grid that is about square.
+pub struct StratifiedSampler {
+    samples: u32,
+    // size of the grid for pairs of dimensions, which has at least one cell for each sample
+    columns: u32,
+    rows: u32,
+    seed: u64,
+    state: SampleState,
+}
+
+impl StratifiedSampler {
+    /// Sampler for taking `samples` samples per pixel, randomized by `seed`.
+    pub fn new(samples: u32, seed: u64) -> StratifiedSampler {
+        let samples = samples.max(1);
+        let columns = ((samples as f32).sqrt().round() as u32).max(1);
+        let rows = samples.div_ceil(columns);
+        StratifiedSampler {
+            samples,
+            columns,
+            rows,
+            seed,
+            state: SampleState::default(),
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, pixel: [u32; 2], index: u32) {
+        self.state.start(pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let h = self.state.dimension_hash(self.seed);
+        let stratum = permutation_element(self.state.index % self.samples, self.samples, h as u32);
+        let jitter = self.state.random(self.seed, 0);
+        self.state.dimension += 1;
+        ((stratum as f32 + jitter) / self.samples as f32).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        let cells = self.columns * self.rows;
+        let h = self.state.dimension_hash(self.seed);
+        let stratum = permutation_element(self.state.index % cells, cells, h as u32);
+        let (x, y) = (stratum % self.columns, stratum / self.columns);
+        let jitter = vec2(
+            self.state.random(self.seed, 0),
+            self.state.random(self.seed, 1),
+        );
+        self.state.dimension += 2;
+        vec2(
+            ((x as f32 + jitter.x) / self.columns as f32).min(ONE_MINUS_EPSILON),
+            ((y as f32 + jitter.y) / self.rows as f32).min(ONE_MINUS_EPSILON),
+        )
+    }
+}
+
+/// Points of the Halton sequence, whose coordinates are the radical inverses of the sample
+/// index in a different prime base for each dimension. The digits get randomly permuted for
+/// each pixel and dimension (Owen scrambling). Dimensions beyond the table of primes, where the
+/// sequence would no longer be well distributed, get independent random numbers.
+pub struct HaltonSampler {
+    seed: u64,
+    state: SampleState,
+}
+
+impl HaltonSampler {
+    /// Sampler whose scrambling is randomized by `seed`.
+    pub fn new(seed: u64) -> HaltonSampler {
+        HaltonSampler {
+            seed,
+            state: SampleState::default(),
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, pixel: [u32; 2], index: u32) {
+        self.state.start(pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let dimension = self.state.dimension as usize;
+        let value = if dimension < PRIMES.len() {
+            let h = self.state.dimension_hash(self.seed);
+            owen_scrambled_radical_inverse(PRIMES[dimension], self.state.index as u64, h)
+        } else {
+            self.state.random(self.seed, 0)
+        };
+        self.state.dimension += 1;
+        value
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        let x = self.get_1d();
+        vec2(x, self.get_1d())
+    }
+}
+
+/// Points of the Sobol sequence, using only its first two dimensions, which are well
+/// distributed both on their own and together. Every other dimension reuses them, with the
+/// order of the samples shuffled, so the dimensions do not correlate. Each dimension also gets
+/// its own Owen scrambling for each pixel.
+pub struct SobolSampler {
+    samples: u32,
+    seed: u64,
+    state: SampleState,
+}
+
+impl SobolSampler {
+    /// Sampler for taking `samples` samples per pixel, randomized by `seed`.
+    pub fn new(samples: u32, seed: u64) -> SobolSampler {
+        SobolSampler {
+            samples: samples.max(1),
+            seed,
+            state: SampleState::default(),
+        }
+    }
+
+    // the index into the sequence that the current sample uses for the current dimension,
+    // along with the hash to scramble its digits with
+    fn shuffled_index(&self) -> (u32, u64) {
+        let h = self.state.dimension_hash(self.seed);
+        let index = self.state.index % self.samples;
+        (permutation_element(index, self.samples, h as u32), h)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, pixel: [u32; 2], index: u32) {
+        self.state.start(pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let (index, h) = self.shuffled_index();
+        self.state.dimension += 1;
+        to_unit_float(fast_owen_scramble(sobol(index, 0), (h >> 32) as u32))
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        let (index, h) = self.shuffled_index();
+        self.state.dimension += 2;
+        vec2(
+            to_unit_float(fast_owen_scramble(sobol(index, 0), (h >> 32) as u32)),
+            to_unit_float(fast_owen_scramble(sobol(index, 1), h as u32)),
+        )
+    }
+}
+
+// largest float below 1
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+// the first primes, one for each dimension of the halton sequence
+const PRIMES: [u64; 128] = first_primes();
+
+const fn first_primes<const N: usize>() -> [u64; N] {
+    let mut primes = [0; N];
+    let mut count = 0;
+    let mut candidate = 2;
+    while count < N {
+        let mut divisor = 2;
+        while divisor * divisor <= candidate && candidate % divisor != 0 {
+            divisor += 1;
+        }
+        if divisor * divisor > candidate {
+            primes[count] = candidate;
+            count += 1;
+        }
+        candidate += 1;
+    }
+    primes
+}
+
+// maps the bits of a 32 bit integer to a float in [0, 1), keeping as many as fit
+fn to_unit_float(bits: u32) -> f32 {
+    (bits >> 8) as f32 / (1u32 << 24) as f32
+}
+
+// scrambles the bits of v (murmurhash3's finalizer)
+fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 33;
+    v = v.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    v ^= v >> 33;
+    v = v.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    v ^= v >> 33;
+    v
+}
+
+fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e37_79b9_7f4a_7c15, |h, &v| {
+        mix_bits(h.rotate_left(23) ^ v)
+    })
+}
+
+// element i of a random permutation of 0..n, picked by the seed, without storing the
+// permutation (Kensler, 2013)
+fn permutation_element(mut i: u32, n: u32, seed: u32) -> u32 {
+    let p = seed;
+    let mut w = n.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % n
+}
+
+// the digits of a in the given base, mirrored around the radix point. each digit gets permuted
+// depending on the digits before it, which is owen scrambling.
+fn owen_scrambled_radical_inverse(base: u64, mut a: u64, hash: u64) -> f32 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_m = 1.0;
+    let mut reversed_digits = 0u64;
+    // keep going until the digits no longer matter at float precision, as the permutations
+    // also change the zero digits past the end of a
+    while inv_base_m > f32::EPSILON as f64 / 4.0 {
+        let next = a / base;
+        let digit = (a - next * base) as u32;
+        let digit_hash = mix_bits(hash ^ reversed_digits) as u32;
+        let digit = permutation_element(digit, base as u32, digit_hash) as u64;
+        reversed_digits = reversed_digits * base + digit;
+        inv_base_m *= inv_base;
+        a = next;
+    }
+    ((reversed_digits as f64 * inv_base_m) as f32).min(ONE_MINUS_EPSILON)
+}
+
+// generator matrices of the first two dimensions of the sobol sequence, as the columns for
+// each bit of the index. the first dimension is the van der corput sequence.
+const SOBOL_MATRICES: [[u32; 32]; 2] = sobol_matrices();
+
+const fn sobol_matrices() -> [[u32; 32]; 2] {
+    let mut matrices = [[0; 32]; 2];
+    let mut v = 1u32 << 31;
+    let mut i = 0;
+    while i < 32 {
+        matrices[0][i] = 1 << (31 - i);
+        matrices[1][i] = v;
+        v ^= v >> 1;
+        i += 1;
+    }
+    matrices
+}
+
+fn sobol(mut index: u32, dimension: usize) -> u32 {
+    let mut v = 0;
+    let mut i = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            v ^= SOBOL_MATRICES[dimension][i];
+        }
+        index >>= 1;
+        i += 1;
+    }
+    v
+}
+
+// scrambles the bits of v, with each bit flipped depending on the bits above it (Laine and
+// Karras, 2011, with the constants from Burley, 2020)
+fn fast_owen_scramble(mut v: u32, seed: u32) -> u32 {
+    v = v.reverse_bits();
+    v ^= v.wrapping_mul(0x3d20_adea);
+    v = v.wrapping_add(seed);
+    v = v.wrapping_mul((seed >> 16) | 1);
+    v ^= v.wrapping_mul(0x0552_6c56);
+    v ^= v.wrapping_mul(0x53a2_2864);
+    v.reverse_bits()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 4] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    // the 2d points that the samples of a pixel get for its first pair of dimensions
+    fn first_points(sampler: &mut dyn Sampler, pixel: [u32; 2], samples: u32) -> Vec<Vec2> {
+        (0..samples)
+            .map(|index| {
+                sampler.start_pixel_sample(pixel, index);
+                sampler.get_2d()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn permutations_are_bijections() {
+        for &n in &[1, 2, 3, 5, 7, 12, 100, 257, 1000] {
+            for seed in 0..20u32 {
+                let mut seen = vec![false; n as usize];
+                for i in 0..n {
+                    let element = permutation_element(i, n, seed.wrapping_mul(0x9e37_79b9));
+                    assert!(element < n, "{} is out of range for {}", element, n);
+                    assert!(
+                        !seen[element as usize],
+                        "{} appears twice for {}",
+                        element, n
+                    );
+                    seen[element as usize] = true;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stratified_samples_fill_every_stratum() {
+        for &samples in &[1, 5, 7, 12, 16] {
+            let sampler = StratifiedSampler::new(samples, 3);
+            let (columns, rows) = (sampler.columns, sampler.rows);
+            let mut sampler: Box<dyn Sampler> = Box::new(sampler);
+            for pixel in [[0, 0], [5, 9]] {
+                let mut cells = vec![false; (columns * rows) as usize];
+                for p in first_points(sampler.as_mut(), pixel, samples) {
+                    let (x, y) = ((p.x * columns as f32) as u32, (p.y * rows as f32) as u32);
+                    let cell = &mut cells[(y * columns + x) as usize];
+                    assert!(!*cell, "two samples in cell ({}, {}) of {}", x, y, samples);
+                    *cell = true;
+                }
+
+                let mut strata = vec![false; samples as usize];
+                for index in 0..samples {
+                    sampler.start_pixel_sample(pixel, index);
+                    sampler.get_2d();
+                    let stratum = (sampler.get_1d() * samples as f32) as usize;
+                    assert!(
+                        !strata[stratum],
+                        "two samples in stratum {} of {}",
+                        stratum, samples
+                    );
+                    strata[stratum] = true;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sobol_samples_fill_every_elementary_interval() {
+        let samples = 64;
+        let mut sampler = SobolSampler::new(samples, 5);
+        for pixel in [[0, 0], [3, 1]] {
+            for dimension in 0..4 {
+                let points: Vec<Vec2> = (0..samples)
+                    .map(|index| {
+                        sampler.start_pixel_sample(pixel, index);
+                        (0..dimension).for_each(|_| {
+                            sampler.get_2d();
+                        });
+                        sampler.get_2d()
+                    })
+                    .collect();
+                // intervals of 2^-a by 2^-(6 - a) each hold exactly one of the 2^6 points
+                for a in 0..=6 {
+                    let (columns, rows) = (1u32 << a, 1u32 << (6 - a));
+                    let mut cells = vec![false; samples as usize];
+                    for p in &points {
+                        let (x, y) = ((p.x * columns as f32) as u32, (p.y * rows as f32) as u32);
+                        let cell = &mut cells[(y * columns + x) as usize];
+                        assert!(!*cell, "two samples in a {}x{} interval", columns, rows);
+                        *cell = true;
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn samples_lie_in_the_unit_interval() {
+        for &kind in &KINDS {
+            for &samples in &[1, 6, 16] {
+                let mut sampler = kind.build(samples, 11);
+                for pixel in [[0, 0], [7, 2], [u32::MAX, 1]] {
+                    for index in 0..samples * 2 {
+                        sampler.start_pixel_sample(pixel, index);
+                        for _ in 0..200 {
+                            let p = sampler.get_2d();
+                            let x = sampler.get_1d();
+                            for v in [p.x, p.y, x] {
+                                assert!((0.0..1.0).contains(&v), "{:?} gave {}", kind, v);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn samples_depend_only_on_seed_pixel_and_index() {
+        let values = |sampler: &mut dyn Sampler, pixel, index| {
+            sampler.start_pixel_sample(pixel, index);
+            (0..20).map(|_| sampler.get_1d()).collect::<Vec<f32>>()
+        };
+        for &kind in &KINDS {
+            let mut first = kind.build(8, 42);
+            let mut second = kind.build(8, 42);
+            // the second sampler visits other pixels first, which must not change anything
+            values(second.as_mut(), [9, 9], 3);
+            for pixel in [[0, 0], [4, 1]] {
+                for index in 0..8 {
+                    let expected = values(first.as_mut(), pixel, index);
+                    assert_eq!(
+                        expected,
+                        values(second.as_mut(), pixel, index),
+                        "{:?}",
+                        kind
+                    );
+                    assert_eq!(expected, values(first.as_mut(), pixel, index), "{:?}", kind);
+                }
+            }
+            let mut other = kind.build(8, 43);
+            assert_ne!(
+                values(first.as_mut(), [0, 0], 0),
+                values(other.as_mut(), [0, 0], 0)
+            );
+        }
+    }
+}
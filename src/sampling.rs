@@ -0,0 +1,73 @@
+//! Turns uniformly distributed random numbers into samples of the distributions that rendering
+//! needs, like directions around a normal or points on a lens. The numbers come from a
+//! [`Sampler`](crate::sampler::Sampler), which is passed along explicitly to everything that
+//! samples.
+//!
+//! The mappings take points in the unit square, and directions are in a local frame around
+//! the z axis unless said otherwise.
+
+use glm::{vec2, vec3, Vec2, Vec3};
+use std::f32::consts::PI;
+
+/// Maps a point in the unit square to a uniformly distributed point in the unit disk, keeping
+/// nearby points close together (Shirley and Chiu, 1997).
+pub fn concentric_disk(u: &Vec2) -> Vec2 {
+    let a = 2.0 * u.x - 1.0;
+    let b = 2.0 * u.y - 1.0;
+    if a == 0.0 && b == 0.0 {
+        return vec2(0.0, 0.0);
+    }
+    let (r, phi) = if a.abs() > b.abs() {
+        (a, PI / 4.0 * (b / a))
+    } else {
+        (b, PI / 2.0 - PI / 4.0 * (a / b))
+    };
+    vec2(r * phi.cos(), r * phi.sin())
+}
+
+/// Maps a point in the unit square to a direction in the hemisphere around the z axis, with a
+/// density proportional to the cosine of its angle with the axis. This lifts a uniformly
+/// distributed point on the disk up onto the hemisphere (Malley's method).
+pub fn cosine_hemisphere(u: &Vec2) -> Vec3 {
+    let d = concentric_disk(u);
+    let z = (1.0 - d.x * d.x - d.y * d.y).max(0.0).sqrt();
+    vec3(d.x, d.y, z)
+}
+
+/// Density of [`cosine_hemisphere`] for a direction with the given cosine with the z axis.
+pub fn cosine_hemisphere_pdf(cos_theta: f32) -> f32 {
+    cos_theta.max(0.0) / PI
+}
+
+/// Maps a point in the unit square to a uniformly distributed direction.
+pub fn uniform_sphere(u: &Vec2) -> Vec3 {
+    let z = 1.0 - 2.0 * u.x;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.y;
+    vec3(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Density of [`uniform_sphere`], which is the same for all directions.
+pub fn uniform_sphere_pdf() -> f32 {
+    1.0 / (4.0 * PI)
+}
+
+/// Maps a point in the unit cube to a uniformly distributed point in the unit ball.
+pub fn uniform_ball(u: &Vec3) -> Vec3 {
+    uniform_sphere(&u.xy()) * u.z.cbrt()
+}
+
+/// Maps a point in the unit square to a uniformly distributed direction within a cone around
+/// the z axis. The cone is given by one minus the cosine of its half angle, which keeps the
+/// precision that the cosine itself loses for narrow cones.
+pub fn uniform_cone(u: &Vec2, one_minus_cos_max: f32) -> Vec3 {
+    let cos_theta = 1.0 - u.x * one_minus_cos_max;
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.y;
+    vec3(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+/// Density of [`uniform_cone`], which is the same for all directions in the cone.
+pub fn uniform_cone_pdf(one_minus_cos_max: f32) -> f32 {
+    1.0 / (2.0 * PI * one_minus_cos_max)
+}
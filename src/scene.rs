@@ -0,0 +1,229 @@
+//! Scenes, and the objects they are made of.
+
+use crate::bvh::{Aabb, Bvh};
+use crate::light::{orthonormal_basis, SurfaceSample};
+use crate::material::Material;
+use crate::ray::Ray;
+use glm::{dot, Vec2, Vec3};
+
+/// Something that can be placed in a scene and hit by rays.
+pub trait SceneObject: Sync + Send {
+    /// Closest intersection with the ray in the interval [t_min, t_max], if any.
+    fn ray_hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>>;
+
+    /// Box containing the whole object. Returns None for objects that extend infinitely.
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    /// Material of the object's surface.
+    fn get_material(&self) -> &dyn Material;
+
+    /// Samples a point on the surface that is visible from `origin`, for use as a light source.
+    /// `u` is a uniformly distributed point in the unit square, and `time` the moment at which
+    /// the object is seen.
+    fn sample(&self, _origin: &Vec3, _u: &Vec2, _time: f32) -> Option<SurfaceSample> {
+        None
+    }
+
+    /// Probability density, in solid angle as seen from `origin`, with which `sample` would
+    /// have picked the point of `hit` on this object at `time`.
+    fn light_pdf(&self, _origin: &Vec3, _hit: &HitRecord, _time: f32) -> f32 {
+        0.0
+    }
+}
+
+/// Description of where a ray hit an object.
+#[derive(Clone, Copy)]
+pub struct HitRecord<'a> {
+    /// Distance along the ray.
+    pub t: f32,
+    /// Point where the ray hit the surface.
+    pub point: Vec3,
+    /// Unit surface normal used for shading, on the side of the surface that the ray came
+    /// from. For smooth shaded triangles it is interpolated from the vertex normals, so it may
+    /// face slightly away from the ray near silhouettes.
+    pub normal: Vec3,
+    /// Unit normal of the surface itself, always facing against the ray.
+    pub geometric_normal: Vec3,
+    /// Surface parameterization at the hit point. For triangles without texture coordinates
+    /// these are the barycentric coordinates of the hit.
+    pub uv: Vec2,
+    /// Unit vectors perpendicular to the outward normal, pointing in the directions in which u
+    /// and v increase along the surface, as far as they can while being perpendicular to each
+    /// other. Together with the outward normal they form the tangent frame that normal maps are
+    /// expressed in. Unlike the normal, they do not flip for hits from the inside.
+    pub tangent: Vec3,
+    /// The vector of the tangent frame that points along v, see [`tangent`](Self::tangent).
+    pub bitangent: Vec3,
+    /// Whether the ray hit the outside of the surface, as told by the geometric normal. For
+    /// hits from the inside, the normals are the flipped outward normals.
+    pub front_face: bool,
+    /// Material of the surface at the hit.
+    pub material: &'a dyn Material,
+    /// The object that was hit.
+    pub object: &'a dyn SceneObject,
+}
+
+impl<'a> HitRecord<'a> {
+    /// Hit record with an arbitrary tangent frame, for surfaces without a parameterization that
+    /// it could follow.
+    pub fn new(
+        ray: &Ray,
+        t: f32,
+        outward_normal: &Vec3,
+        uv: Vec2,
+        object: &'a dyn SceneObject,
+    ) -> HitRecord<'a> {
+        let front_face = dot(&ray.direction, outward_normal) < 0.0;
+        let normal = if front_face {
+            *outward_normal
+        } else {
+            -outward_normal
+        };
+        let (tangent, bitangent) = orthonormal_basis(outward_normal);
+        HitRecord {
+            t,
+            point: ray.point_at(t),
+            normal,
+            geometric_normal: normal,
+            uv,
+            tangent,
+            bitangent,
+            front_face,
+            material: object.get_material(),
+            object,
+        }
+    }
+
+    /// Sets the outward normal used for shading, keeping the geometric normal for telling which
+    /// side of the surface the ray hit. Resets the tangent frame to an arbitrary one around the
+    /// new normal.
+    pub fn with_shading_normal(self, outward_normal: &Vec3) -> Self {
+        let normal = if self.front_face {
+            *outward_normal
+        } else {
+            -outward_normal
+        };
+        let (tangent, bitangent) = orthonormal_basis(outward_normal);
+        HitRecord {
+            normal,
+            tangent,
+            bitangent,
+            ..self
+        }
+    }
+
+    /// Sets the tangent frame from the directions in which the point moves as u and v increase,
+    /// which need not be unit length or perpendicular. Keeps the arbitrary frame if they are
+    /// degenerate.
+    pub fn with_tangents(self, dpdu: &Vec3, dpdv: &Vec3) -> Self {
+        let normal = self.outward_normal();
+        let tangent = dpdu - normal * dot(&normal, dpdu);
+        let length2 = glm::length2(&tangent);
+        if !(length2 > 1e-12 && length2.is_finite()) {
+            return self;
+        }
+        let tangent = glm::normalize(&tangent);
+        let bitangent = normal.cross(&tangent);
+        // mirrored uvs make v run the other way
+        let bitangent = if dot(&bitangent, dpdv) < 0.0 {
+            -bitangent
+        } else {
+            bitangent
+        };
+        HitRecord {
+            tangent,
+            bitangent,
+            ..self
+        }
+    }
+
+    /// The normal facing out of the object, regardless of which side the ray came from.
+    pub fn outward_normal(&self) -> Vec3 {
+        if self.front_face {
+            self.normal
+        } else {
+            -self.normal
+        }
+    }
+}
+
+/// What rays that do not hit anything see.
+pub enum Background {
+    /// Sky fading from white at the horizon to blue at the top.
+    Gradient,
+    /// The same color in every direction.
+    Color(Vec3),
+}
+
+impl Background {
+    /// Light arriving along a ray that does not hit anything.
+    pub fn color(&self, ray: &Ray) -> Vec3 {
+        match self {
+            Background::Gradient => background_color_gradient(ray),
+            Background::Color(c) => *c,
+        }
+    }
+}
+
+fn background_color_gradient(ray: &Ray) -> Vec3 {
+    let unit_dir: Vec3 = glm::normalize(&ray.direction);
+    let ground_color: Vec3 = Vec3::new(1.0, 1.0, 1.0);
+    let sky_color: Vec3 = Vec3::new(0.2, 0.4, 0.8);
+    let t = 0.5 * (unit_dir.y + 1.0);
+    glm::lerp(&ground_color, &sky_color, t)
+}
+
+/// All objects in the scene, along with an acceleration structure to quickly find which of
+/// them a ray may hit.
+pub struct Scene {
+    // objects without bounds (like planes) can not be put in the bvh, so those are kept apart
+    // and always tested.
+    pub(crate) objects: Vec<Box<dyn SceneObject>>,
+    unbounded: Vec<Box<dyn SceneObject>>,
+    bvh: Bvh,
+    // indices of the emissive objects, which get sampled for direct lighting.
+    // infinite objects can not be sampled, so those only contribute when hit by chance.
+    pub(crate) lights: Vec<usize>,
+    /// What rays see when they do not hit any object.
+    pub background: Background,
+}
+
+impl Scene {
+    /// Scene of the given objects, in front of a [`Background::Gradient`].
+    pub fn new(objects: Vec<Box<dyn SceneObject>>) -> Scene {
+        let (objects, unbounded): (Vec<_>, Vec<_>) = objects
+            .into_iter()
+            .partition(|o| o.bounding_box().is_some());
+        let bounds: Vec<Aabb> = objects.iter().filter_map(|o| o.bounding_box()).collect();
+        let bvh = Bvh::new(&bounds);
+        let lights = (0..objects.len())
+            .filter(|&i| objects[i].get_material().is_emissive())
+            .collect();
+        Scene {
+            objects,
+            unbounded,
+            bvh,
+            lights,
+            background: Background::Gradient,
+        }
+    }
+
+    /// Closest hit of the ray with any object in the scene, within [min_t, max_t].
+    pub fn hit(&self, ray: &Ray, min_t: f32, max_t: f32) -> Option<HitRecord<'_>> {
+        // determine closest hit among the unbounded objects first
+        let mut closest = max_t;
+        let mut result: Option<HitRecord> = None;
+        for obj in self.unbounded.iter() {
+            if let Some(h) = obj.ray_hit(ray, min_t, closest) {
+                closest = h.t;
+                result = Some(h);
+            }
+        }
+        // then only test the objects whose bounds the ray passes through
+        self.bvh
+            .hit(ray, min_t, closest, |i, closest| {
+                self.objects[i].ray_hit(ray, min_t, closest)
+            })
+            .or(result)
+    }
+}
@@ -0,0 +1,1831 @@
+//! Loads scenes from json files, so they can be changed without recompiling. A scene file
+//! describes the camera, materials, objects, lights and render settings, for example:
+//!
+//! ```json
+//! {
+//!     "render": { "width": 800, "height": 400, "samples": 64, "output": "target/out.png" },
+//!     "camera": { "eye": [0, 5, 20], "target": [0, 1, 0], "fov": 40 },
+//!     "materials": {
+//!         "white": { "type": "diffuse", "albedo": [0.8, 0.8, 0.8] },
+//!         "earth": { "type": "diffuse", "albedo": { "image": "earth.jpg" } },
+//!         "marble": { "type": "diffuse", "albedo": { "type": "marble", "scale": 2 } },
+//!         "paint": { "type": "principled", "base_color": [0.6, 0.05, 0.05], "clearcoat": 1 },
+//!         "glass": { "type": "dielectric", "ior": 1.5 }
+//!     },
+//!     "objects": [
+//!         { "type": "plane", "point": [0, 0, 0], "normal": [0, 1, 0], "material": "white" },
+//!         { "type": "sphere", "center": [0, 1, 0], "radius": 1, "material": "glass" },
+//!         { "type": "mesh", "file": "teapot.obj" }
+//!     ],
+//!     "lights": [
+//!         { "type": "sphere", "center": [0, 8, 0], "radius": 1, "emission": [10, 10, 10] }
+//!     ]
+//! }
+//! ```
+//!
+//! Meshes take their materials from the .mtl files they reference, unless a material is given.
+//! Relative paths to mesh and image files are resolved relative to the scene file.
+
+use crate::mesh::Triangle;
+use crate::motion::{Animated, Keyframe, Motion, Transform};
+use crate::obj;
+use crate::{
+    AdaptiveSampling, Background, Camera, Checker, ColorRamp, Conductor, Dielectric, Diffuse,
+    Emissive, Equirectangular, Eye, Fisheye, FisheyeMapping, ImageTexture, Material, Metal,
+    NoisePattern, NormalMap, NormalMapped, Ods, Orthographic, Perspective, Plane, Principled,
+    Procedural, RenderSettings, Rig, SamplerKind, Scene, SceneObject, Sphere, StereoLayout,
+    Texture, View, WrapMode,
+};
+use glm::{vec2, vec3, Vec3};
+use serde::de::value::{MapAccessDeserializer, MapDeserializer, SeqAccessDeserializer};
+use serde::de::{
+    self, DeserializeSeed, Deserializer, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+/// Error loading a scene file. Invalid entries are reported along with their location in the
+/// file, like `objects[2].material`.
+#[derive(Debug)]
+pub enum SceneError {
+    /// The scene file could not be read.
+    Io {
+        /// The scene file.
+        path: PathBuf,
+        /// Why it could not be read.
+        error: io::Error,
+    },
+    /// The file is not valid json, or does not match the expected structure.
+    Parse {
+        /// The scene file.
+        path: PathBuf,
+        /// Path to the offending entry, like `objects[2].radius`.
+        entry: String,
+        /// What is wrong with the entry.
+        error: serde_json::Error,
+    },
+    /// The file is well-formed, but one of its entries makes no sense.
+    Invalid {
+        /// The scene file.
+        path: PathBuf,
+        /// Path to the offending entry.
+        entry: String,
+        /// What is wrong with the entry.
+        message: String,
+    },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            SceneError::Parse { path, entry, error } => {
+                write!(f, "{}: {}: {}", path.display(), entry, error)
+            }
+            SceneError::Invalid {
+                path,
+                entry,
+                message,
+            } => write!(f, "{}: {}: {}", path.display(), entry, message),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SceneError::Io { error, .. } => Some(error),
+            SceneError::Parse { error, .. } => Some(error),
+            SceneError::Invalid { .. } => None,
+        }
+    }
+}
+
+/// Everything needed to render the image described by a scene file.
+pub struct LoadedScene {
+    /// The objects and lights of the scene.
+    pub scene: Scene,
+    /// The camera or cameras to render the scene with.
+    pub rig: Rig,
+    /// Size, quality and output of the render.
+    pub settings: RenderSettings,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    #[serde(default)]
+    render: RenderDesc,
+    #[serde(default)]
+    camera: CameraDesc,
+    // solid background color. without one, the default sky gradient is used.
+    background: Option<[f32; 3]>,
+    #[serde(default)]
+    materials: HashMap<String, MaterialDesc>,
+    #[serde(default)]
+    objects: Vec<ObjectDesc>,
+    #[serde(default)]
+    lights: Vec<LightDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RenderDesc {
+    width: u32,
+    height: u32,
+    samples: u32,
+    max_depth: u32,
+    // one of independent, stratified, halton or sobol. without one, the renderer's default is
+    // used.
+    sampler: Option<SamplerDesc>,
+    seed: u64,
+    // sampling pixels only until their relative error drops below `threshold`, taking at least
+    // `min_samples` and at most `samples` samples
+    adaptive: Option<AdaptiveDesc>,
+    output: PathBuf,
+    // image of the number of samples taken for each pixel
+    heatmap: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AdaptiveDesc {
+    min_samples: u32,
+    threshold: f32,
+}
+
+impl Default for AdaptiveDesc {
+    fn default() -> Self {
+        let adaptive = AdaptiveSampling::default();
+        AdaptiveDesc {
+            min_samples: adaptive.min_samples,
+            threshold: adaptive.threshold,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum SamplerDesc {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl Default for RenderDesc {
+    fn default() -> Self {
+        let settings = RenderSettings::default();
+        RenderDesc {
+            width: settings.width,
+            height: settings.height,
+            samples: settings.samples,
+            max_depth: settings.max_depth,
+            sampler: None,
+            seed: settings.seed,
+            adaptive: None,
+            output: settings.output,
+            heatmap: settings.heatmap,
+        }
+    }
+}
+
+// camera at `eye` looking at `target`, with the projection one of perspective, orthographic,
+// fisheye, equirectangular or ods (omnidirectional stereo).
+// - perspective cameras have `fov`, the vertical field of view in degrees. a non-zero aperture
+//   radius gives depth of field, with things at the focus distance (by default the distance to
+//   the target) in focus. with blades, the aperture is a polygon rotated by `blade_rotation`
+//   degrees.
+// - orthographic cameras have `height`, the size in world units of the area the image covers.
+// - fisheye cameras have `fov`, the angle covered by the image circle, and an equidistant or
+//   equisolid `mapping`.
+// perspective and orthographic cameras follow the aspect ratio of the image, unless `aspect` is
+// given. the shutter is open between the two given times, blurring objects that move meanwhile.
+// perspective cameras render in stereo when given `stereo` settings, and ods cameras always do.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CameraDesc {
+    projection: ProjectionDesc,
+    eye: [f32; 3],
+    target: [f32; 3],
+    up: [f32; 3],
+    fov: Option<f32>,
+    aspect: Option<f32>,
+    height: Option<f32>,
+    mapping: Option<FisheyeMappingDesc>,
+    aperture: f32,
+    focus_distance: Option<f32>,
+    blades: u32,
+    blade_rotation: f32,
+    shutter: [f32; 2],
+    stereo: Option<StereoDesc>,
+}
+
+impl Default for CameraDesc {
+    fn default() -> Self {
+        CameraDesc {
+            projection: ProjectionDesc::Perspective,
+            eye: [0.0, 5.0, 20.0],
+            target: [0.0, -1.2, 0.0],
+            up: [0.0, 1.0, 0.0],
+            fov: None,
+            aspect: None,
+            height: None,
+            mapping: None,
+            aperture: 0.0,
+            focus_distance: None,
+            blades: 0,
+            blade_rotation: 0.0,
+            shutter: [0.0, 0.0],
+            stereo: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum ProjectionDesc {
+    Perspective,
+    Orthographic,
+    Fisheye,
+    Equirectangular,
+    Ods,
+}
+
+// eyes `interocular` apart, converging at distance `convergence` (by default the distance to
+// the target), with the images for both eyes in separate files or packed top/bottom in one.
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+struct StereoDesc {
+    interocular: f32,
+    convergence: Option<f32>,
+    layout: StereoLayoutDesc,
+}
+
+impl Default for StereoDesc {
+    fn default() -> Self {
+        StereoDesc {
+            interocular: 0.064,
+            convergence: None,
+            layout: StereoLayoutDesc::TopBottom,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum StereoLayoutDesc {
+    Separate,
+    TopBottom,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum FisheyeMappingDesc {
+    Equidistant,
+    Equisolid,
+}
+
+// conductors are physically based metals, with their complex index of refraction `eta + ik`
+// either given or taken from a preset. they and dielectrics have a roughness from 0 for a
+// perfectly smooth surface to 1. principled materials blend between all kinds of surfaces.
+// parameters that are a single number, like the roughness, can be textures too, which then
+// give the number as the average of their color channels.
+#[derive(Deserialize)]
+#[serde(remote = "Self", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Diffuse {
+        albedo: TextureDesc,
+        normal_map: Option<TextureDesc>,
+        bump_map: Option<BumpMapDesc>,
+    },
+    Metal {
+        albedo: TextureDesc,
+        #[serde(default = "default_zero")]
+        scattering: TextureDesc,
+        normal_map: Option<TextureDesc>,
+        bump_map: Option<BumpMapDesc>,
+    },
+    Conductor {
+        preset: Option<ConductorPresetDesc>,
+        eta: Option<[f32; 3]>,
+        k: Option<[f32; 3]>,
+        #[serde(default = "default_zero")]
+        roughness: TextureDesc,
+        normal_map: Option<TextureDesc>,
+        bump_map: Option<BumpMapDesc>,
+    },
+    Dielectric {
+        ior: f32,
+        #[serde(default = "default_zero")]
+        roughness: TextureDesc,
+        normal_map: Option<TextureDesc>,
+        bump_map: Option<BumpMapDesc>,
+    },
+    Principled(Box<PrincipledDesc>),
+    Emissive {
+        emission: TextureDesc,
+    },
+}
+
+fn default_zero() -> TextureDesc {
+    TextureDesc::Number(0.0)
+}
+
+// parameters of the principled material, which all go from 0 to 1 except for the ior and the
+// emission. they default to a gray plastic, like the material's own defaults.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PrincipledDesc {
+    base_color: TextureDesc,
+    metallic: TextureDesc,
+    roughness: TextureDesc,
+    specular: TextureDesc,
+    sheen: TextureDesc,
+    clearcoat: TextureDesc,
+    clearcoat_roughness: TextureDesc,
+    transmission: TextureDesc,
+    ior: f32,
+    emission: Option<TextureDesc>,
+    normal_map: Option<TextureDesc>,
+    bump_map: Option<BumpMapDesc>,
+}
+
+impl Default for PrincipledDesc {
+    fn default() -> Self {
+        PrincipledDesc {
+            base_color: TextureDesc::Color([0.8, 0.8, 0.8]),
+            metallic: TextureDesc::Number(0.0),
+            roughness: TextureDesc::Number(0.5),
+            specular: TextureDesc::Number(0.5),
+            sheen: TextureDesc::Number(0.0),
+            clearcoat: TextureDesc::Number(0.0),
+            clearcoat_roughness: TextureDesc::Number(0.03),
+            transmission: TextureDesc::Number(0.0),
+            ior: Principled::default().ior,
+            emission: None,
+            normal_map: None,
+            bump_map: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum ConductorPresetDesc {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+}
+
+// surfaces get detail from either a tangent space normal map, or a grayscale bump map whose
+// heights are scaled by `strength` in units of uv. both are textures holding data rather than
+// colors, so their images are taken to be stored linearly.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BumpMapDesc {
+    height: TextureDesc,
+    #[serde(default = "default_bump_strength")]
+    strength: f32,
+}
+
+fn default_bump_strength() -> f32 {
+    0.01
+}
+
+// a texture is either a constant number or color, an image mapped onto the surface through its
+// uvs, or a procedural texture. a number is a gray color. beyond the edges of an image, it
+// repeats or clamps to the edge pixels.
+enum TextureDesc {
+    Number(f32),
+    Color([f32; 3]),
+    Image(ImageTextureDesc),
+    Procedural(ProceduralDesc),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ImageTextureDesc {
+    image: PathBuf,
+    wrap: Option<WrapModeDesc>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum WrapModeDesc {
+    Repeat,
+    Clamp,
+}
+
+// a checkerboard alternates between two textures in squares of size `scale`, laid out over the
+// uvs, or filling space with cubes if it is `solid`. the other procedural textures are made from
+// noise, filling space with features of about size `scale`.
+#[derive(Deserialize)]
+#[serde(remote = "Self", rename_all = "snake_case", deny_unknown_fields)]
+enum ProceduralDesc {
+    Checker {
+        even: Box<TextureDesc>,
+        odd: Box<TextureDesc>,
+        #[serde(default = "default_scale")]
+        scale: f32,
+        #[serde(default)]
+        solid: bool,
+    },
+    Noise(NoiseDesc),
+    Turbulence(NoiseDesc),
+    Marble(NoiseDesc),
+    Wood(NoiseDesc),
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+// turbulence, marble and wood add up `octaves` layers of noise, and marble and wood bend their
+// stripes and rings by `distortion` times that. the resulting values between 0 and 1 get their
+// color from `ramp`, a list of [position, color] pairs sorted by position, black to white by
+// default. different seeds give different noise.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct NoiseDesc {
+    scale: f32,
+    octaves: Option<u32>,
+    distortion: Option<f32>,
+    ramp: Vec<(f32, [f32; 3])>,
+    seed: u64,
+}
+
+impl Default for NoiseDesc {
+    fn default() -> Self {
+        NoiseDesc {
+            scale: 1.0,
+            octaves: None,
+            distortion: None,
+            ramp: vec![(0.0, [0.0, 0.0, 0.0]), (1.0, [1.0, 1.0, 1.0])],
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(remote = "Self", rename_all = "snake_case", deny_unknown_fields)]
+enum ObjectDesc {
+    Sphere {
+        center: [f32; 3],
+        radius: f32,
+        material: String,
+        motion: Option<MotionDesc>,
+    },
+    Plane {
+        point: [f32; 3],
+        normal: [f32; 3],
+        material: String,
+    },
+    Triangle {
+        vertices: [[f32; 3]; 3],
+        normals: Option<[[f32; 3]; 3]>,
+        uvs: Option<[[f32; 2]; 3]>,
+        material: String,
+        motion: Option<MotionDesc>,
+    },
+    Mesh {
+        file: PathBuf,
+        // overrides the materials from the mesh's .mtl files
+        material: Option<String>,
+        motion: Option<MotionDesc>,
+    },
+}
+
+// lights are emissive spheres or triangles, which can also be described as objects with an
+// emissive material. this is just a more convenient way of writing them down.
+#[derive(Deserialize)]
+#[serde(remote = "Self", rename_all = "snake_case", deny_unknown_fields)]
+enum LightDesc {
+    Sphere {
+        center: [f32; 3],
+        radius: f32,
+        emission: [f32; 3],
+        motion: Option<MotionDesc>,
+    },
+    Triangle {
+        vertices: [[f32; 3]; 3],
+        emission: [f32; 3],
+        motion: Option<MotionDesc>,
+    },
+}
+
+// how an object moves while the camera shutter is open. a linear motion moves the object from
+// where it is at rest to its transform at `end`, starting at `start`, while keyframes give the
+// transform at several times. transforms scale the object by `scale`, then rotate it around
+// `axis` by `angle` degrees, then move it by `offset`, all relative to the origin. rotations
+// take the shortest way between keyframes, so turning by half a turn or more takes more of them.
+#[derive(Deserialize)]
+#[serde(remote = "Self", rename_all = "snake_case", deny_unknown_fields)]
+enum MotionDesc {
+    Linear {
+        #[serde(default)]
+        start: f32,
+        #[serde(default = "default_motion_end")]
+        end: f32,
+        #[serde(flatten)]
+        transform: TransformDesc,
+    },
+    Keyframes {
+        keyframes: Vec<KeyframeDesc>,
+    },
+}
+
+fn default_motion_end() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyframeDesc {
+    time: f32,
+    #[serde(flatten)]
+    transform: TransformDesc,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct TransformDesc {
+    offset: [f32; 3],
+    rotation: Option<RotationDesc>,
+    scale: [f32; 3],
+}
+
+impl Default for TransformDesc {
+    fn default() -> Self {
+        TransformDesc {
+            offset: [0.0, 0.0, 0.0],
+            rotation: None,
+            scale: [1.0, 1.0, 1.0],
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RotationDesc {
+    axis: [f32; 3],
+    angle: f32,
+}
+
+// the enums whose variant is named by a `type` entry are derived as externally tagged enums
+// with `remote = "Self"`, and read through `TaggedVisitor`. serde's own internally tagged enums
+// read the whole entry before looking at its type, after which errors no longer know which
+// field they are in. reading the type first keeps them pointing at the field, like
+// `materials.white.albedo`.
+trait Tagged: Sized {
+    // reads the variant from an enum with the tag as its variant and the other entries as its
+    // fields
+    fn deserialize_variant<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error>;
+}
+
+macro_rules! tagged {
+    ($($name:ident),*) => {$(
+        impl Tagged for $name {
+            fn deserialize_variant<'de, D: Deserializer<'de>>(
+                deserializer: D,
+            ) -> Result<Self, D::Error> {
+                $name::deserialize(deserializer)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                deserializer.deserialize_map(TaggedVisitor(PhantomData))
+            }
+        }
+    )*};
+}
+
+tagged!(
+    MaterialDesc,
+    ProceduralDesc,
+    ObjectDesc,
+    LightDesc,
+    MotionDesc
+);
+
+struct TaggedVisitor<T>(PhantomData<T>);
+
+impl<'de, T: Tagged> Visitor<'de> for TaggedVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an object with a type")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<T, A::Error> {
+        let key = map.next_key()?;
+        read_tagged(key, map)
+    }
+}
+
+// reads a tagged enum from the entries of a map, the first of which has already been read up to
+// its key. the type usually comes first, and then the other entries are read as they come.
+// otherwise they are gathered up to find the type, and errors in them lose their location.
+fn read_tagged<'de, T: Tagged, A: MapAccess<'de>>(
+    first: Option<String>,
+    mut map: A,
+) -> Result<T, A::Error> {
+    if first.as_deref() == Some("type") {
+        let tag = map.next_value()?;
+        return T::deserialize_variant(VariantDeserializer { tag, fields: map });
+    }
+    let mut entries = serde_json::Map::new();
+    let mut key = first;
+    while let Some(k) = key {
+        entries.insert(k, map.next_value()?);
+        key = map.next_key()?;
+    }
+    let tag = match entries.remove("type") {
+        Some(serde_json::Value::String(tag)) => tag,
+        Some(_) => return Err(de::Error::custom("type must be a string")),
+        None => return Err(de::Error::missing_field("type")),
+    };
+    let fields = MapDeserializer::new(entries.into_iter());
+    T::deserialize_variant(VariantDeserializer { tag, fields }).map_err(de::Error::custom)
+}
+
+// an externally tagged enum, made from the tag and the map of fields that follow it
+struct VariantDeserializer<A> {
+    tag: String,
+    fields: A,
+}
+
+impl<'de, A: MapAccess<'de>> Deserializer<'de> for VariantDeserializer<A> {
+    type Error = A::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, A::Error> {
+        visitor.visit_enum(self)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map struct enum identifier
+        ignored_any
+    }
+}
+
+impl<'de, A: MapAccess<'de>> EnumAccess<'de> for VariantDeserializer<A> {
+    type Error = A::Error;
+    type Variant = VariantFields<A>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, VariantFields<A>), A::Error> {
+        let variant = seed.deserialize(self.tag.into_deserializer())?;
+        Ok((variant, VariantFields(self.fields)))
+    }
+}
+
+struct VariantFields<A>(A);
+
+impl<'de, A: MapAccess<'de>> VariantAccess<'de> for VariantFields<A> {
+    type Error = A::Error;
+
+    fn unit_variant(mut self) -> Result<(), A::Error> {
+        match self.0.next_key::<String>()? {
+            Some(key) => Err(de::Error::unknown_field(&key, &[])),
+            None => Ok(()),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, A::Error> {
+        seed.deserialize(MapAccessDeserializer::new(self.0))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, A::Error> {
+        Err(de::Error::invalid_type(de::Unexpected::Map, &visitor))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, A::Error> {
+        visitor.visit_map(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for TextureDesc {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(TextureVisitor)
+    }
+}
+
+struct TextureVisitor;
+
+impl<'de> Visitor<'de> for TextureVisitor {
+    type Value = TextureDesc;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a number, a color array or a texture object")
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<TextureDesc, E> {
+        Ok(TextureDesc::Number(value as f32))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<TextureDesc, E> {
+        Ok(TextureDesc::Number(value as f32))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<TextureDesc, E> {
+        Ok(TextureDesc::Number(value as f32))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<TextureDesc, A::Error> {
+        Deserialize::deserialize(SeqAccessDeserializer::new(seq)).map(TextureDesc::Color)
+    }
+
+    // images have an `image` entry, and procedural textures a type
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<TextureDesc, A::Error> {
+        let key: Option<String> = map.next_key()?;
+        match key.as_deref() {
+            Some("image") | Some("wrap") => {
+                let entries = FirstKey { key, map };
+                Deserialize::deserialize(MapAccessDeserializer::new(entries))
+                    .map(TextureDesc::Image)
+            }
+            _ => read_tagged(key, map).map(TextureDesc::Procedural),
+        }
+    }
+}
+
+// the entries of a map whose first key has already been read
+struct FirstKey<A> {
+    key: Option<String>,
+    map: A,
+}
+
+impl<'de, A: MapAccess<'de>> MapAccess<'de> for FirstKey<A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, A::Error> {
+        match self.key.take() {
+            Some(key) => seed.deserialize(key.into_deserializer()).map(Some),
+            None => self.map.next_key_seed(seed),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, A::Error> {
+        self.map.next_value_seed(seed)
+    }
+}
+
+// errors in building motions and transforms come with the path to the field they are about,
+// relative to the motion or transform, like `.keyframes[1].scale`
+type FieldError = (String, String);
+
+fn field_error(path: &str, message: &str) -> FieldError {
+    (path.to_string(), message.to_string())
+}
+
+impl TransformDesc {
+    fn build(&self) -> Result<Transform, FieldError> {
+        if self.scale.iter().any(|&s| s == 0.0 || !s.is_finite()) {
+            return Err(field_error(".scale", "scale must be non-zero"));
+        }
+        let transform =
+            Transform::translation(to_vec3(&self.offset)).with_scale(to_vec3(&self.scale));
+        match &self.rotation {
+            Some(rotation) => {
+                let axis = to_vec3(&rotation.axis);
+                if rotation.axis.iter().all(|&a| a == 0.0) {
+                    return Err(field_error(
+                        ".rotation.axis",
+                        "rotation axis can not be zero",
+                    ));
+                }
+                Ok(transform.with_rotation(&axis, rotation.angle))
+            }
+            None => Ok(transform),
+        }
+    }
+}
+
+impl MotionDesc {
+    fn build(&self) -> Result<Motion, FieldError> {
+        match self {
+            MotionDesc::Linear {
+                start,
+                end,
+                transform,
+            } => {
+                if end < start {
+                    return Err(field_error(".end", "end can not be before start"));
+                }
+                Ok(Motion::Linear {
+                    start: *start,
+                    end: *end,
+                    transform: transform.build()?,
+                })
+            }
+            MotionDesc::Keyframes { keyframes } => {
+                if keyframes.is_empty() {
+                    return Err(field_error(".keyframes", "need at least one keyframe"));
+                }
+                if let Some(i) = keyframes.windows(2).position(|k| k[0].time >= k[1].time) {
+                    return Err(field_error(
+                        &format!(".keyframes[{}].time", i + 1),
+                        "keyframes must be sorted by time",
+                    ));
+                }
+                let keyframes = keyframes
+                    .iter()
+                    .enumerate()
+                    .map(|(i, k)| {
+                        let transform = k.transform.build().map_err(|(path, message)| {
+                            (format!(".keyframes[{}]{}", i, path), message)
+                        })?;
+                        Ok(Keyframe {
+                            time: k.time,
+                            transform,
+                        })
+                    })
+                    .collect::<Result<_, FieldError>>()?;
+                Ok(Motion::Keyframes(keyframes))
+            }
+        }
+    }
+}
+
+fn to_vec3(v: &[f32; 3]) -> Vec3 {
+    vec3(v[0], v[1], v[2])
+}
+
+fn is_color(c: &[f32; 3]) -> bool {
+    c.iter().all(|&x| x >= 0.0 && x.is_finite())
+}
+
+impl NoiseDesc {
+    // checks the settings, where only layered patterns have octaves and only distorted ones
+    // have a distortion
+    fn validate(&self, layered: bool, distorted: bool) -> Result<(), &'static str> {
+        if self.scale <= 0.0 {
+            return Err("scale must be positive");
+        }
+        match self.octaves {
+            Some(_) if !layered => return Err("octaves only apply to turbulence, marble and wood"),
+            Some(0) => return Err("needs at least one octave"),
+            _ => {}
+        }
+        if self.distortion.is_some() && !distorted {
+            return Err("distortion only applies to marble and wood");
+        }
+        if self.ramp.is_empty() {
+            return Err("ramp needs at least one color");
+        }
+        if self.ramp.windows(2).any(|s| s[0].0 > s[1].0) {
+            return Err("ramp must be sorted by position");
+        }
+        if !self.ramp.iter().all(|(_, color)| is_color(color)) {
+            return Err("ramp colors can not be negative");
+        }
+        Ok(())
+    }
+}
+
+impl PrincipledDesc {
+    fn validate(&self) -> Result<(), String> {
+        for (name, texture) in self.parameters().iter() {
+            texture.validate_number(name)?;
+        }
+        if self.ior <= 0.0 {
+            return Err("ior must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    // the parameters that go from 0 to 1, along with their fields
+    fn parameters(&self) -> [(&'static str, &TextureDesc); 7] {
+        [
+            ("metallic", &self.metallic),
+            ("roughness", &self.roughness),
+            ("specular", &self.specular),
+            ("sheen", &self.sheen),
+            ("clearcoat", &self.clearcoat),
+            ("clearcoat_roughness", &self.clearcoat_roughness),
+            ("transmission", &self.transmission),
+        ]
+    }
+}
+
+impl TextureDesc {
+    // checks the texture in the given field of a material
+    fn validate(&self, field: &str) -> Result<(), String> {
+        let error = |message: &str| Err(format!("{} {}", field, message));
+        match self {
+            TextureDesc::Number(number) if *number < 0.0 || !number.is_finite() => {
+                error("can not be negative")
+            }
+            TextureDesc::Color(color) if !is_color(color) => error("can not be negative"),
+            TextureDesc::Number(_) | TextureDesc::Color(_) | TextureDesc::Image(_) => Ok(()),
+            TextureDesc::Procedural(ProceduralDesc::Checker {
+                even, odd, scale, ..
+            }) => {
+                if *scale <= 0.0 {
+                    return error("scale must be positive");
+                }
+                even.validate(&format!("{}.even", field))?;
+                odd.validate(&format!("{}.odd", field))
+            }
+            TextureDesc::Procedural(ProceduralDesc::Noise(noise)) => {
+                noise.validate(false, false).or_else(error)
+            }
+            TextureDesc::Procedural(ProceduralDesc::Turbulence(noise)) => {
+                noise.validate(true, false).or_else(error)
+            }
+            TextureDesc::Procedural(ProceduralDesc::Marble(noise))
+            | TextureDesc::Procedural(ProceduralDesc::Wood(noise)) => {
+                noise.validate(true, true).or_else(error)
+            }
+        }
+    }
+
+    // checks that a constant number in the given field of a material is between 0 and 1.
+    // textures that vary get clamped to that range where they are used.
+    fn validate_number(&self, field: &str) -> Result<(), String> {
+        match self {
+            TextureDesc::Number(number) if !(0.0..=1.0).contains(number) => {
+                Err(format!("{} must be between 0 and 1", field))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // whether the texture is black everywhere, for emission that gives off no light
+    fn is_black(&self) -> bool {
+        match self {
+            TextureDesc::Number(number) => *number == 0.0,
+            TextureDesc::Color(color) => color.iter().all(|&c| c == 0.0),
+            _ => false,
+        }
+    }
+
+    // image textures in this texture, including those nested in checkerboards
+    fn images(&self) -> Vec<&ImageTextureDesc> {
+        match self {
+            TextureDesc::Image(desc) => vec![desc],
+            TextureDesc::Procedural(ProceduralDesc::Checker { even, odd, .. }) => {
+                let mut images = even.images();
+                images.extend(odd.images());
+                images
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    // images are loaded up front, so objects sharing them also share the loaded image. they get
+    // decoded with the given gamma.
+    fn build(&self, images: &HashMap<PathBuf, ImageTexture>, gamma: f32) -> Box<dyn Texture> {
+        let procedural = match self {
+            TextureDesc::Number(number) => return Box::new(*number),
+            TextureDesc::Color(color) => return Box::new(to_vec3(color)),
+            TextureDesc::Image(desc) => {
+                let wrap = match desc.wrap.unwrap_or(WrapModeDesc::Repeat) {
+                    WrapModeDesc::Repeat => WrapMode::Repeat,
+                    WrapModeDesc::Clamp => WrapMode::Clamp,
+                };
+                let image = images[&desc.image].clone();
+                return Box::new(image.with_wrap(wrap).with_gamma(gamma));
+            }
+            TextureDesc::Procedural(procedural) => procedural,
+        };
+        let (noise, pattern) = match procedural {
+            ProceduralDesc::Checker {
+                even,
+                odd,
+                scale,
+                solid,
+            } => {
+                let (even, odd) = (even.build(images, gamma), odd.build(images, gamma));
+                return if *solid {
+                    Box::new(Checker::solid(even, odd, *scale))
+                } else {
+                    Box::new(Checker::uv(even, odd, *scale))
+                };
+            }
+            ProceduralDesc::Noise(noise) => (noise, NoisePattern::Noise),
+            ProceduralDesc::Turbulence(noise) => (
+                noise,
+                NoisePattern::Turbulence {
+                    octaves: noise.octaves.unwrap_or(6),
+                },
+            ),
+            ProceduralDesc::Marble(noise) => (
+                noise,
+                NoisePattern::Marble {
+                    octaves: noise.octaves.unwrap_or(6),
+                    distortion: noise.distortion.unwrap_or(5.0),
+                },
+            ),
+            ProceduralDesc::Wood(noise) => (
+                noise,
+                NoisePattern::Wood {
+                    octaves: noise.octaves.unwrap_or(3),
+                    distortion: noise.distortion.unwrap_or(0.3),
+                },
+            ),
+        };
+        let ramp = noise
+            .ramp
+            .iter()
+            .map(|(position, color)| (*position, to_vec3(color)))
+            .collect();
+        Box::new(
+            Procedural::new(pattern, ColorRamp::new(ramp))
+                .with_scale(noise.scale)
+                .with_seed(noise.seed),
+        )
+    }
+}
+
+impl MaterialDesc {
+    fn normal_maps(&self) -> (Option<&TextureDesc>, Option<&BumpMapDesc>) {
+        match self {
+            MaterialDesc::Diffuse {
+                normal_map,
+                bump_map,
+                ..
+            }
+            | MaterialDesc::Metal {
+                normal_map,
+                bump_map,
+                ..
+            }
+            | MaterialDesc::Conductor {
+                normal_map,
+                bump_map,
+                ..
+            }
+            | MaterialDesc::Dielectric {
+                normal_map,
+                bump_map,
+                ..
+            } => (normal_map.as_ref(), bump_map.as_ref()),
+            MaterialDesc::Principled(principled) => {
+                (principled.normal_map.as_ref(), principled.bump_map.as_ref())
+            }
+            MaterialDesc::Emissive { .. } => (None, None),
+        }
+    }
+
+    // textures of the material, along with the fields they are in
+    fn textures(&self) -> Vec<(&'static str, &TextureDesc)> {
+        let mut textures = match self {
+            MaterialDesc::Diffuse { albedo, .. } => vec![("albedo", albedo)],
+            MaterialDesc::Metal {
+                albedo, scattering, ..
+            } => vec![("albedo", albedo), ("scattering", scattering)],
+            MaterialDesc::Conductor { roughness, .. }
+            | MaterialDesc::Dielectric { roughness, .. } => vec![("roughness", roughness)],
+            MaterialDesc::Principled(principled) => {
+                let mut textures = vec![("base_color", &principled.base_color)];
+                textures.extend(principled.parameters().iter());
+                textures.extend(principled.emission.as_ref().map(|e| ("emission", e)));
+                textures
+            }
+            MaterialDesc::Emissive { emission } => vec![("emission", emission)],
+        };
+        let (normal_map, bump_map) = self.normal_maps();
+        textures.extend(normal_map.map(|texture| ("normal_map", texture)));
+        textures.extend(bump_map.map(|bump| ("bump_map.height", &bump.height)));
+        textures
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        for (field, texture) in self.textures() {
+            texture.validate(field)?;
+        }
+        if let MaterialDesc::Conductor { roughness, .. }
+        | MaterialDesc::Dielectric { roughness, .. } = self
+        {
+            roughness.validate_number("roughness")?;
+        }
+        match self.normal_maps() {
+            (Some(_), Some(_)) => {
+                return Err("can not have both a normal map and a bump map".to_string());
+            }
+            (_, Some(bump)) if !bump.strength.is_finite() => {
+                return Err("bump_map.strength must be a number".to_string());
+            }
+            _ => {}
+        }
+        match self {
+            MaterialDesc::Conductor { preset, eta, k, .. } => match (preset, eta, k) {
+                (Some(_), None, None) => Ok(()),
+                (None, Some(eta), Some(k)) if is_color(eta) && is_color(k) => Ok(()),
+                (None, Some(_), Some(_)) => Err("eta and k can not be negative".to_string()),
+                _ => Err("conductors need either a preset, or both eta and k".to_string()),
+            },
+            MaterialDesc::Dielectric { ior, .. } if *ior <= 0.0 => {
+                Err("ior must be positive".to_string())
+            }
+            MaterialDesc::Principled(principled) => principled.validate(),
+            _ => Ok(()),
+        }
+    }
+
+    fn build(&self, images: &HashMap<PathBuf, ImageTexture>) -> Box<dyn Material> {
+        let material: Box<dyn Material> = match self {
+            MaterialDesc::Diffuse { albedo, .. } => Box::new(Diffuse {
+                albedo: albedo.build(images, 2.2),
+            }),
+            MaterialDesc::Metal {
+                albedo, scattering, ..
+            } => Box::new(Metal {
+                albedo: albedo.build(images, 2.2),
+                scattering: scattering.build(images, 1.0),
+            }),
+            MaterialDesc::Conductor {
+                preset,
+                eta,
+                k,
+                roughness,
+                ..
+            } => {
+                let roughness = roughness.build(images, 1.0);
+                Box::new(match (preset, eta, k) {
+                    (Some(ConductorPresetDesc::Gold), _, _) => Conductor::gold(roughness),
+                    (Some(ConductorPresetDesc::Copper), _, _) => Conductor::copper(roughness),
+                    (Some(ConductorPresetDesc::Aluminium), _, _) => Conductor::aluminium(roughness),
+                    (Some(ConductorPresetDesc::Silver), _, _) => Conductor::silver(roughness),
+                    (None, eta, k) => Conductor {
+                        eta: to_vec3(&eta.unwrap_or_default()),
+                        k: to_vec3(&k.unwrap_or_default()),
+                        roughness,
+                    },
+                })
+            }
+            MaterialDesc::Dielectric { ior, roughness, .. } => Box::new(Dielectric {
+                ior: *ior,
+                roughness: roughness.build(images, 1.0),
+            }),
+            MaterialDesc::Principled(principled) => Box::new(Principled {
+                base_color: principled.base_color.build(images, 2.2),
+                metallic: principled.metallic.build(images, 1.0),
+                roughness: principled.roughness.build(images, 1.0),
+                specular: principled.specular.build(images, 1.0),
+                sheen: principled.sheen.build(images, 1.0),
+                clearcoat: principled.clearcoat.build(images, 1.0),
+                clearcoat_roughness: principled.clearcoat_roughness.build(images, 1.0),
+                transmission: principled.transmission.build(images, 1.0),
+                ior: principled.ior,
+                // black emission would make the object a light that never gives off any light
+                emission: principled
+                    .emission
+                    .as_ref()
+                    .filter(|emission| !emission.is_black())
+                    .map(|emission| emission.build(images, 2.2)),
+            }),
+            MaterialDesc::Emissive { emission } => Box::new(Emissive {
+                emission: emission.build(images, 2.2),
+            }),
+        };
+        let map = match self.normal_maps() {
+            (Some(normal_map), _) => NormalMap::Tangent(normal_map.build(images, 1.0)),
+            (None, Some(bump)) => NormalMap::Bump {
+                height: bump.height.build(images, 1.0),
+                strength: bump.strength,
+            },
+            (None, None) => return material,
+        };
+        Box::new(NormalMapped { material, map })
+    }
+}
+
+// turns the parsed description into a scene, checking everything that the json structure
+// itself can not express. errors name the entry they are about.
+struct SceneBuilder<'a> {
+    path: &'a Path,
+    desc: &'a SceneDesc,
+    objects: Vec<Box<dyn SceneObject>>,
+    // images used by textures, by their path in the scene file
+    images: HashMap<PathBuf, ImageTexture>,
+}
+
+impl<'a> SceneBuilder<'a> {
+    fn error(&self, entry: String, message: String) -> SceneError {
+        SceneError::Invalid {
+            path: self.path.to_path_buf(),
+            entry,
+            message,
+        }
+    }
+
+    // resolves a path from the scene file relative to the scene file
+    fn resolve(&self, file: &Path) -> PathBuf {
+        let dir = self.path.parent().unwrap_or_else(|| Path::new(""));
+        dir.join(file)
+    }
+
+    // loads the images for the textures of the material, unless another material already did
+    fn load_images(&mut self, name: &str, material: &MaterialDesc) -> Result<(), SceneError> {
+        for (field, texture) in material.textures() {
+            for desc in texture.images() {
+                if self.images.contains_key(&desc.image) {
+                    continue;
+                }
+                let path = self.resolve(&desc.image);
+                let image = ImageTexture::load(&path).map_err(|e| {
+                    self.error(
+                        format!("materials.{}.{}", name, field),
+                        format!("{}: {}", path.display(), e),
+                    )
+                })?;
+                self.images.insert(desc.image.clone(), image);
+            }
+        }
+        Ok(())
+    }
+
+    fn material(&self, entry: &str, name: &str) -> Result<Box<dyn Material>, SceneError> {
+        match self.desc.materials.get(name) {
+            Some(m) => Ok(m.build(&self.images)),
+            None => Err(self.error(
+                format!("{}.material", entry),
+                format!("unknown material '{}'", name),
+            )),
+        }
+    }
+
+    // adds the object to the scene, making it move if it has a motion
+    fn push(
+        &mut self,
+        entry: &str,
+        object: Box<dyn SceneObject>,
+        motion: &Option<MotionDesc>,
+    ) -> Result<(), SceneError> {
+        let object: Box<dyn SceneObject> = match motion {
+            Some(motion) => {
+                let motion = motion.build().map_err(|(path, message)| {
+                    self.error(format!("{}.motion{}", entry, path), message)
+                })?;
+                Box::new(Animated::new(object, motion))
+            }
+            None => object,
+        };
+        self.objects.push(object);
+        Ok(())
+    }
+
+    // corners of a triangle, which must not all lie on a line. triangles without area could
+    // never be hit, nor be sampled as lights.
+    fn triangle_vertices(
+        &self,
+        entry: &str,
+        vertices: &[[f32; 3]; 3],
+    ) -> Result<[Vec3; 3], SceneError> {
+        let [a, b, c] = [
+            to_vec3(&vertices[0]),
+            to_vec3(&vertices[1]),
+            to_vec3(&vertices[2]),
+        ];
+        let area = glm::length(&(b - a).cross(&(c - a)));
+        if area == 0.0 || !area.is_finite() {
+            return Err(self.error(
+                format!("{}.vertices", entry),
+                "triangle has no area".to_string(),
+            ));
+        }
+        Ok([a, b, c])
+    }
+
+    fn add_sphere(
+        &mut self,
+        entry: String,
+        center: &[f32; 3],
+        radius: f32,
+        material: Box<dyn Material>,
+        motion: &Option<MotionDesc>,
+    ) -> Result<(), SceneError> {
+        if radius <= 0.0 {
+            return Err(self.error(entry, "radius must be positive".to_string()));
+        }
+        let sphere = Box::new(Sphere {
+            position: to_vec3(center),
+            radius,
+            material,
+        });
+        self.push(&entry, sphere, motion)
+    }
+
+    fn add_object(&mut self, index: usize, object: &ObjectDesc) -> Result<(), SceneError> {
+        let entry = format!("objects[{}]", index);
+        match object {
+            ObjectDesc::Sphere {
+                center,
+                radius,
+                material,
+                motion,
+            } => {
+                let material = self.material(&entry, material)?;
+                self.add_sphere(entry, center, *radius, material, motion)?;
+            }
+            ObjectDesc::Plane {
+                point,
+                normal,
+                material,
+            } => {
+                if glm::length(&to_vec3(normal)) == 0.0 {
+                    return Err(self.error(entry, "normal can not be zero".to_string()));
+                }
+                let material = self.material(&entry, material)?;
+                self.objects.push(Box::new(Plane {
+                    point: to_vec3(point),
+                    normal: to_vec3(normal),
+                    material,
+                }));
+            }
+            ObjectDesc::Triangle {
+                vertices,
+                normals,
+                uvs,
+                material,
+                motion,
+            } => {
+                let vertices = self.triangle_vertices(&entry, vertices)?;
+                let material = self.material(&entry, material)?;
+                let triangle = Box::new(Triangle {
+                    vertices,
+                    normals: normals.map(|n| [to_vec3(&n[0]), to_vec3(&n[1]), to_vec3(&n[2])]),
+                    uvs: uvs.map(|uv| {
+                        [
+                            vec2(uv[0][0], uv[0][1]),
+                            vec2(uv[1][0], uv[1][1]),
+                            vec2(uv[2][0], uv[2][1]),
+                        ]
+                    }),
+                    material,
+                });
+                self.push(&entry, triangle, motion)?;
+            }
+            ObjectDesc::Mesh {
+                file,
+                material,
+                motion,
+            } => {
+                let meshes = obj::load_obj(&self.resolve(file))
+                    .map_err(|e| self.error(format!("{}.file", entry), e.to_string()))?;
+                for mesh in meshes {
+                    let mesh = match material {
+                        Some(name) => mesh.with_material(self.material(&entry, name)?),
+                        None => mesh,
+                    };
+                    self.push(&entry, Box::new(mesh), motion)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn rig(&self) -> Result<Rig, SceneError> {
+        let cam = &self.desc.camera;
+        let stereo = match (&cam.stereo, cam.projection) {
+            (None, ProjectionDesc::Ods) => StereoDesc::default(),
+            (None, _) => return Ok(Rig::Mono(self.camera(None)?)),
+            (Some(stereo), ProjectionDesc::Perspective) | (Some(stereo), ProjectionDesc::Ods) => {
+                stereo.clone()
+            }
+            (Some(_), _) => {
+                return Err(self.error(
+                    "camera.stereo".to_string(),
+                    "only perspective and ods cameras can render in stereo".to_string(),
+                ))
+            }
+        };
+        let error = |field: &str, message: &str| {
+            self.error(format!("camera.stereo.{}", field), message.to_string())
+        };
+        if stereo.interocular < 0.0 {
+            return Err(error("interocular", "must not be negative"));
+        }
+        match stereo.convergence {
+            Some(_) if cam.projection == ProjectionDesc::Ods => {
+                return Err(error("convergence", "ods cameras do not converge"));
+            }
+            Some(convergence) if convergence <= 0.0 => {
+                return Err(error("convergence", "must be positive"));
+            }
+            _ => {}
+        }
+        Ok(Rig::Stereo {
+            left: self.camera(Some(Eye::Left))?,
+            right: self.camera(Some(Eye::Right))?,
+            layout: match stereo.layout {
+                StereoLayoutDesc::Separate => StereoLayout::Separate,
+                StereoLayoutDesc::TopBottom => StereoLayout::TopBottom,
+            },
+        })
+    }
+
+    // the camera for the given eye, or the only camera when not rendering in stereo
+    fn camera(&self, eye: Option<Eye>) -> Result<Box<dyn Camera>, SceneError> {
+        let cam = &self.desc.camera;
+        let error = |field: &str, message: &str| {
+            self.error(format!("camera.{}", field), message.to_string())
+        };
+        let (eye_position, target, up) =
+            (to_vec3(&cam.eye), to_vec3(&cam.target), to_vec3(&cam.up));
+        if eye_position == target {
+            return Err(self.error(
+                "camera".to_string(),
+                "eye and target must be different points".to_string(),
+            ));
+        }
+        if glm::length2(&up.cross(&(target - eye_position))) == 0.0 {
+            return Err(error("up", "must not be parallel to the viewing direction"));
+        }
+        if cam.shutter[1] < cam.shutter[0] {
+            return Err(error("shutter", "shutter can not close before it opens"));
+        }
+        let view =
+            View::look_at(eye_position, target, up).with_shutter(cam.shutter[0], cam.shutter[1]);
+
+        // settings that only apply to some projections
+        let projection = cam.projection;
+        let perspective = projection == ProjectionDesc::Perspective;
+        if cam.fov.is_some() && !perspective && projection != ProjectionDesc::Fisheye {
+            return Err(error(
+                "fov",
+                "only perspective and fisheye cameras have a fov",
+            ));
+        }
+        if cam.aspect.is_some() && !perspective && projection != ProjectionDesc::Orthographic {
+            return Err(error(
+                "aspect",
+                "only perspective and orthographic cameras have an aspect ratio",
+            ));
+        }
+        if cam.height.is_some() && projection != ProjectionDesc::Orthographic {
+            return Err(error("height", "only orthographic cameras have a height"));
+        }
+        if cam.mapping.is_some() && projection != ProjectionDesc::Fisheye {
+            return Err(error("mapping", "only fisheye cameras have a mapping"));
+        }
+        if !perspective && (cam.aperture != 0.0 || cam.focus_distance.is_some() || cam.blades != 0)
+        {
+            return Err(error("aperture", "only perspective cameras have a lens"));
+        }
+        if let Some(aspect) = cam.aspect {
+            if aspect <= 0.0 {
+                return Err(error("aspect", "must be positive"));
+            }
+        }
+
+        let camera: Box<dyn Camera> = match projection {
+            ProjectionDesc::Perspective => {
+                let fov = cam.fov.unwrap_or(60.0);
+                if !(fov > 0.0 && fov < 180.0) {
+                    return Err(error("fov", "must be between 0 and 180 degrees"));
+                }
+                if cam.aperture < 0.0 {
+                    return Err(error("aperture", "must not be negative"));
+                }
+                if cam.blades == 1 || cam.blades == 2 {
+                    return Err(error(
+                        "blades",
+                        "need at least 3 blades, or 0 for a round aperture",
+                    ));
+                }
+                let mut camera = Perspective::new(view, fov)
+                    .with_aperture(cam.aperture)
+                    .with_blades(cam.blades, cam.blade_rotation);
+                if let Some(distance) = cam.focus_distance {
+                    if distance <= 0.0 {
+                        return Err(error("focus_distance", "must be positive"));
+                    }
+                    camera = camera.with_focus_distance(distance);
+                }
+                if let Some(aspect) = cam.aspect {
+                    camera = camera.with_aspect_ratio(aspect);
+                }
+                if let (Some(eye), Some(stereo)) = (eye, &cam.stereo) {
+                    let convergence = stereo
+                        .convergence
+                        .unwrap_or_else(|| glm::distance(&eye_position, &target));
+                    camera = camera.with_stereo_eye(eye, stereo.interocular, convergence);
+                }
+                Box::new(camera)
+            }
+            ProjectionDesc::Orthographic => {
+                let height = match cam.height {
+                    Some(height) if height > 0.0 => height,
+                    Some(_) => return Err(error("height", "must be positive")),
+                    None => return Err(error("height", "orthographic cameras need a height")),
+                };
+                let mut camera = Orthographic::new(view, height);
+                if let Some(aspect) = cam.aspect {
+                    camera = camera.with_aspect_ratio(aspect);
+                }
+                Box::new(camera)
+            }
+            ProjectionDesc::Fisheye => {
+                let fov = cam.fov.unwrap_or(180.0);
+                if !(fov > 0.0 && fov <= 360.0) {
+                    return Err(error("fov", "must be between 0 and 360 degrees"));
+                }
+                let mapping = match cam.mapping.unwrap_or(FisheyeMappingDesc::Equisolid) {
+                    FisheyeMappingDesc::Equidistant => FisheyeMapping::Equidistant,
+                    FisheyeMappingDesc::Equisolid => FisheyeMapping::Equisolid,
+                };
+                Box::new(Fisheye::new(view, mapping, fov))
+            }
+            ProjectionDesc::Equirectangular => Box::new(Equirectangular::new(view)),
+            ProjectionDesc::Ods => {
+                let interocular = cam.stereo.as_ref().map_or(0.064, |s| s.interocular);
+                Box::new(Ods::new(view, eye.unwrap_or(Eye::Left), interocular))
+            }
+        };
+        Ok(camera)
+    }
+
+    fn add_light(&mut self, index: usize, light: &LightDesc) -> Result<(), SceneError> {
+        let entry = format!("lights[{}]", index);
+        let emission = match light {
+            LightDesc::Sphere { emission, .. } | LightDesc::Triangle { emission, .. } => emission,
+        };
+        if !is_color(emission) {
+            return Err(self.error(
+                format!("{}.emission", entry),
+                "emission can not be negative".to_string(),
+            ));
+        }
+        let material = Box::new(Emissive {
+            emission: Box::new(to_vec3(emission)),
+        });
+        match light {
+            LightDesc::Sphere {
+                center,
+                radius,
+                motion,
+                ..
+            } => {
+                self.add_sphere(entry, center, *radius, material, motion)?;
+            }
+            LightDesc::Triangle {
+                vertices, motion, ..
+            } => {
+                let triangle = Box::new(Triangle {
+                    vertices: self.triangle_vertices(&entry, vertices)?,
+                    normals: None,
+                    uvs: None,
+                    material,
+                });
+                self.push(&entry, triangle, motion)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Loads the scene, camera and render settings from a json scene file.
+pub fn load_scene(path: &Path) -> Result<LoadedScene, SceneError> {
+    let text = fs::read_to_string(path).map_err(|error| SceneError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    parse_scene(&text, path)
+}
+
+// builds the scene from the text of the scene file at the given path
+fn parse_scene(text: &str, path: &Path) -> Result<LoadedScene, SceneError> {
+    let deserializer = &mut serde_json::Deserializer::from_str(text);
+    let desc: SceneDesc =
+        serde_path_to_error::deserialize(deserializer).map_err(|e| SceneError::Parse {
+            path: path.to_path_buf(),
+            entry: e.path().to_string(),
+            error: e.into_inner(),
+        })?;
+
+    let mut builder = SceneBuilder {
+        path,
+        desc: &desc,
+        objects: Vec::new(),
+        images: HashMap::new(),
+    };
+    let mut names: Vec<&String> = desc.materials.keys().collect();
+    names.sort();
+    for name in names {
+        let material = &desc.materials[name];
+        material
+            .validate()
+            .map_err(|message| builder.error(format!("materials.{}", name), message))?;
+        builder.load_images(name, material)?;
+    }
+    for (i, object) in desc.objects.iter().enumerate() {
+        builder.add_object(i, object)?;
+    }
+    for (i, light) in desc.lights.iter().enumerate() {
+        builder.add_light(i, light)?;
+    }
+
+    let render = &desc.render;
+    if render.width == 0 || render.height == 0 {
+        return Err(builder.error(
+            "render".to_string(),
+            "width and height must be positive".to_string(),
+        ));
+    }
+    if render.samples == 0 {
+        return Err(builder.error(
+            "render.samples".to_string(),
+            "need at least one sample per pixel".to_string(),
+        ));
+    }
+    if let Some(adaptive) = &render.adaptive {
+        if adaptive.min_samples == 0 {
+            return Err(builder.error(
+                "render.adaptive.min_samples".to_string(),
+                "need at least one sample per pixel".to_string(),
+            ));
+        }
+        // negated, so that nan fails the check too
+        #[allow(clippy::neg_cmp_op_on_partial_ord)]
+        if !(adaptive.threshold > 0.0) || !adaptive.threshold.is_finite() {
+            return Err(builder.error(
+                "render.adaptive.threshold".to_string(),
+                "threshold must be positive".to_string(),
+            ));
+        }
+    }
+    let settings = RenderSettings {
+        width: render.width,
+        height: render.height,
+        samples: render.samples,
+        max_depth: render.max_depth,
+        sampler: match render.sampler {
+            Some(SamplerDesc::Independent) => SamplerKind::Independent,
+            Some(SamplerDesc::Stratified) => SamplerKind::Stratified,
+            Some(SamplerDesc::Halton) => SamplerKind::Halton,
+            Some(SamplerDesc::Sobol) => SamplerKind::Sobol,
+            None => RenderSettings::default().sampler,
+        },
+        seed: render.seed,
+        adaptive: render.adaptive.as_ref().map(|adaptive| AdaptiveSampling {
+            min_samples: adaptive.min_samples,
+            threshold: adaptive.threshold,
+        }),
+        output: render.output.clone(),
+        heatmap: render.heatmap.clone(),
+    };
+
+    let rig = builder.rig()?;
+
+    let mut scene = Scene::new(builder.objects);
+    if let Some(color) = &desc.background {
+        scene.background = Background::Color(to_vec3(color));
+    }
+    Ok(LoadedScene {
+        scene,
+        rig,
+        settings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(text: &str) -> String {
+        match parse_scene(text, Path::new("scene.json")) {
+            Ok(_) => panic!("scene loaded without errors"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn loads_textures_of_every_kind() {
+        let text = r#"{"materials": {
+            "plain": {"type": "principled", "base_color": [1, 0, 0], "roughness": 0.3},
+            "mixed": {"type": "principled", "metallic": {"type": "checker", "even": 0, "odd": 1}},
+            "later": {"albedo": {"scale": 2, "type": "marble"}, "type": "diffuse"}
+        }}"#;
+        assert!(parse_scene(text, Path::new("scene.json")).is_ok());
+    }
+
+    #[test]
+    fn texture_errors_name_the_field() {
+        let text = r#"{"materials": {"w": {"type": "diffuse", "albedo": "red"}}}"#;
+        assert_eq!(
+            parse_error(text),
+            "scene.json: materials.w.albedo: invalid type: string \"red\", expected a number, \
+             a color array or a texture object at line 1 column 55"
+        );
+    }
+
+    #[test]
+    fn nested_texture_errors_name_the_nested_field() {
+        let text = r#"{"materials": {"w": {"type": "diffuse",
+            "albedo": {"type": "checker", "even": [1, 1, 1], "odd": [1, 1]}}}}"#;
+        assert_eq!(
+            parse_error(text),
+            "scene.json: materials.w.albedo.odd: invalid length 2, expected an array of length 3 \
+             at line 2 column 74"
+        );
+    }
+
+    #[test]
+    fn object_errors_name_the_field() {
+        let text = r#"{"objects": [{"type": "sphere", "center": [0, 0, 0], "radius": "x",
+            "material": "w"}]}"#;
+        assert_eq!(
+            parse_error(text),
+            "scene.json: objects[0].radius: invalid type: string \"x\", expected f32 \
+             at line 1 column 66"
+        );
+    }
+
+    #[test]
+    fn unknown_types_are_reported() {
+        let text = r#"{"materials": {"w": {"type": "shiny"}}}"#;
+        assert_eq!(
+            parse_error(text),
+            "scene.json: materials.w: unknown variant `shiny`, expected one of `diffuse`, \
+             `metal`, `conductor`, `dielectric`, `principled`, `emissive` at line 1 column 37"
+        );
+    }
+
+    #[test]
+    fn invalid_materials_are_reported() {
+        let text = r#"{"materials": {"w": {"type": "dielectric", "ior": 1.5, "roughness": 2}}}"#;
+        assert_eq!(
+            parse_error(text),
+            "scene.json: materials.w: roughness must be between 0 and 1"
+        );
+    }
+
+    #[test]
+    fn unknown_materials_are_reported() {
+        let text = r#"{"objects": [{"type": "sphere", "center": [0, 0, 0], "radius": 1,
+            "material": "w"}]}"#;
+        assert_eq!(
+            parse_error(text),
+            "scene.json: objects[0].material: unknown material 'w'"
+        );
+    }
+
+    #[test]
+    fn motion_errors_name_the_field() {
+        let sphere = r#"{"materials": {"w": {"type": "diffuse", "albedo": [1, 1, 1]}},
+            "objects": [{"type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "w",
+            "motion": MOTION}]}"#;
+        let cases = [
+            (
+                r#"{"type": "linear", "start": 1, "end": 0}"#,
+                "objects[0].motion.end: end can not be before start",
+            ),
+            (
+                r#"{"type": "keyframes", "keyframes": [{"time": 0}, {"time": 1, "scale": [1, 0, 1]}]}"#,
+                "objects[0].motion.keyframes[1].scale: scale must be non-zero",
+            ),
+            (
+                r#"{"type": "keyframes", "keyframes": [{"time": 0}, {"time": 1}, {"time": 1}]}"#,
+                "objects[0].motion.keyframes[2].time: keyframes must be sorted by time",
+            ),
+            (
+                r#"{"type": "linear", "rotation": {"axis": [0, 0, 0], "angle": 90}}"#,
+                "objects[0].motion.rotation.axis: rotation axis can not be zero",
+            ),
+        ];
+        for (motion, message) in cases.iter() {
+            let text = sphere.replace("MOTION", motion);
+            assert_eq!(parse_error(&text), format!("scene.json: {}", message));
+        }
+    }
+
+    #[test]
+    fn triangles_without_area_are_rejected() {
+        let text = r#"{"lights": [{"type": "triangle", "emission": [1, 1, 1],
+            "vertices": [[0, 0, 0], [1, 1, 1], [2, 2, 2]]}]}"#;
+        assert_eq!(
+            parse_error(text),
+            "scene.json: lights[0].vertices: triangle has no area"
+        );
+    }
+
+    #[test]
+    fn adaptive_threshold_must_be_positive() {
+        let text = r#"{"render": {"adaptive": {"threshold": 0}}}"#;
+        assert_eq!(
+            parse_error(text),
+            "scene.json: render.adaptive.threshold: threshold must be positive"
+        );
+    }
+}
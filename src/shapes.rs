@@ -0,0 +1,195 @@
+//! Shapes defined by a few numbers, as opposed to the triangles of meshes.
+
+use crate::bvh::Aabb;
+use crate::light::{area_to_solid_angle, orthonormal_basis, SurfaceSample};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sampling::{uniform_cone, uniform_cone_pdf, uniform_sphere};
+use crate::scene::{HitRecord, SceneObject};
+use glm::{dot, normalize, vec2, vec3, Vec2, Vec3};
+use std::f32::consts::PI;
+
+/// Sphere around `position`. Its uvs wrap around it like longitude and latitude, with the
+/// seam at -x.
+pub struct Sphere {
+    /// Center of the sphere.
+    pub position: Vec3,
+    /// Radius of the sphere.
+    pub radius: f32,
+    /// Material of the sphere's surface.
+    pub material: Box<dyn Material>,
+}
+
+impl SceneObject for Sphere {
+    fn ray_hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        // define sphere with center C and radius R where all point P on sphere satisfy:
+        //            ||P-C||^2 = R^2
+        // or: dot((P-C),(P-C)) = R^2
+        //
+        // determine intersection with Ray by replacing P with A+tB and solving for t:
+        // dot((A+tB-C), (A+tB-C)) = R^2
+        // t^2 * dot(B,B) + 2t * dot(B,A-C) + dot(A-C,A-C) - r^2 = 0
+        //
+        // which we can also write as:
+        // a(t^2) + bt + c = 0
+        // where
+        // a = dot(B,B)
+        // b = 2*dot(B,A-C)
+        // c = dot(A-C,A-C) - r^2
+        //
+        // so we can use the standard abc quadratic formula
+        //     -b +- srt(b^2 - 4ac)
+        // t = --------------------
+        //             2a
+        let ac = ray.origin - self.position;
+        let a = dot(&ray.direction, &ray.direction);
+        let b = 2.0 * dot(&ac, &ray.direction);
+        let c = dot(&ac, &ac) - self.radius * self.radius;
+        let discr = b * b - 4.0 * a * c;
+        if discr < 0.0 {
+            return None;
+        }
+        // try the nearest intersection first. if that one lies behind the ray origin, the ray
+        // may have started inside the sphere, in which case it exits at the far intersection.
+        let sqrt_discr = discr.sqrt();
+        let mut t = (-b - sqrt_discr) / (2.0 * a);
+        if t > t_max || t < t_min {
+            t = (-b + sqrt_discr) / (2.0 * a);
+            if t > t_max || t < t_min {
+                return None;
+            }
+        }
+        let p = ray.point_at(t);
+        let n = (p - self.position) / self.radius;
+        // u runs around the y axis, and v from the bottom to the top
+        let dpdu = vec3(n.z, 0.0, -n.x);
+        let dpdv = vec3(0.0, 1.0, 0.0) - n * n.y;
+        Some(HitRecord::new(ray, t, &n, sphere_uv(&n), self).with_tangents(&dpdu, &dpdv))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = vec3(self.radius, self.radius, self.radius);
+        Some(Aabb::new(self.position - r, self.position + r))
+    }
+
+    fn get_material(&self) -> &dyn Material {
+        self.material.as_ref()
+    }
+
+    fn sample(&self, origin: &Vec3, u: &Vec2, _time: f32) -> Option<SurfaceSample> {
+        let to_center = self.position - origin;
+        let distance2 = glm::length2(&to_center);
+        let radius2 = self.radius * self.radius;
+        if distance2 <= radius2 {
+            // from the inside the whole sphere is visible, so pick any point on its surface
+            let normal = uniform_sphere(u);
+            let point = self.position + normal * self.radius;
+            let pdf_area = 1.0 / (4.0 * PI * radius2);
+            return Some(SurfaceSample {
+                point,
+                normal,
+                uv: sphere_uv(&normal),
+                pdf: area_to_solid_angle(pdf_area, origin, &point, &normal),
+            });
+        }
+
+        // from the outside, pick a direction uniformly within the cone of directions in which
+        // we see the sphere, and find the point on the sphere in that direction.
+        let distance = distance2.sqrt();
+        let w = to_center / distance;
+        let sin2_max = radius2 / distance2;
+        let cos_max = (1.0 - sin2_max).max(0.0).sqrt();
+        // written this way to keep precision for small or distant spheres
+        let one_minus_cos_max = sin2_max / (1.0 + cos_max);
+        let d = uniform_cone(u, one_minus_cos_max);
+        let (cos_theta, sin2_theta) = (d.z, d.x * d.x + d.y * d.y);
+        let (tx, ty) = orthonormal_basis(&w);
+        let direction = tx * d.x + ty * d.y + w * d.z;
+        let t = distance * cos_theta - (radius2 - distance2 * sin2_theta).max(0.0).sqrt();
+        let point = origin + direction * t;
+        let normal = normalize(&(point - self.position));
+        Some(SurfaceSample {
+            point,
+            normal,
+            uv: sphere_uv(&normal),
+            pdf: uniform_cone_pdf(one_minus_cos_max),
+        })
+    }
+
+    fn light_pdf(&self, origin: &Vec3, hit: &HitRecord, _time: f32) -> f32 {
+        let distance2 = glm::distance2(&self.position, origin);
+        let radius2 = self.radius * self.radius;
+        if distance2 <= radius2 {
+            let pdf_area = 1.0 / (4.0 * PI * radius2);
+            area_to_solid_angle(pdf_area, origin, &hit.point, &hit.geometric_normal)
+        } else {
+            let sin2_max = radius2 / distance2;
+            let cos_max = (1.0 - sin2_max).max(0.0).sqrt();
+            uniform_cone_pdf(sin2_max / (1.0 + cos_max))
+        }
+    }
+}
+
+// maps the unit normal onto the sphere's uvs. u goes around the y axis, starting and ending
+// at -x, and v goes up from 0 at the bottom to 1 at the top.
+fn sphere_uv(n: &Vec3) -> Vec2 {
+    let u = (f32::atan2(-n.z, n.x) + PI) / (2.0 * PI);
+    let v = f32::acos(-n.y.clamp(-1.0, 1.0)) / PI;
+    vec2(u, v)
+}
+
+// directions along a plane with the unit normal n, in which its u and v increase. v follows
+// the y axis up the plane where it can, which leaves the x axis for u on horizontal planes.
+fn plane_axes(n: &Vec3) -> (Vec3, Vec3) {
+    if n.y.abs() < 0.999 {
+        let bitangent = normalize(&(vec3(0.0, 1.0, 0.0) - n * n.y));
+        (bitangent.cross(n), bitangent)
+    } else {
+        let tangent = normalize(&(vec3(1.0, 0.0, 0.0) - n * n.x));
+        (tangent, n.cross(&tangent))
+    }
+}
+
+/// Infinite plane through `point`, facing in the direction of `normal`. Its uvs measure the
+/// distance from `point` along the plane, so textures repeat once per unit of distance. On
+/// walls, u runs to the right and v upwards as seen from the front, while on floors u follows
+/// the x axis.
+pub struct Plane {
+    /// Any point on the plane.
+    pub point: Vec3,
+    /// Direction perpendicular to the plane, on its front side. It does not need to be
+    /// normalized.
+    pub normal: Vec3,
+    /// Material of the plane.
+    pub material: Box<dyn Material>,
+}
+
+impl SceneObject for Plane {
+    fn ray_hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        // all points P on the plane satisfy dot(P-Q, N) = 0, with Q a point on the plane.
+        // replacing P with A+tB and solving for t gives:
+        // t = dot(Q-A, N) / dot(B, N)
+        let denom = dot(&ray.direction, &self.normal);
+        if denom.abs() < 1e-8 {
+            // ray runs parallel to the plane
+            return None;
+        }
+        let t = dot(&(self.point - ray.origin), &self.normal) / denom;
+        if t > t_max || t < t_min {
+            return None;
+        }
+        let normal = normalize(&self.normal);
+        let (tangent, bitangent) = plane_axes(&normal);
+        let offset = ray.point_at(t) - self.point;
+        let uv = vec2(dot(&offset, &tangent), dot(&offset, &bitangent));
+        Some(HitRecord::new(ray, t, &normal, uv, self).with_tangents(&tangent, &bitangent))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+
+    fn get_material(&self) -> &dyn Material {
+        self.material.as_ref()
+    }
+}
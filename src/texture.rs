@@ -0,0 +1,417 @@
+//! Textures make material parameters vary over a surface. They are looked up at each hit with
+//! the surface parameterization (uv), the hit point and the normal, so they can be mapped onto
+//! a surface through its uvs, like images, or be defined throughout space, like the procedural
+//! textures made from noise.
+
+use glm::{vec3, Vec2, Vec3};
+use image::{ImageResult, RgbImage};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::f32::consts::PI;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Color that varies over a surface.
+pub trait Texture: Send + Sync {
+    /// Color at the hit with the given uv, point and normal.
+    fn value(&self, uv: &Vec2, point: &Vec3, normal: &Vec3) -> Vec3;
+
+    /// Number at the hit, for textures of parameters that are a single number rather than a
+    /// color. This is the average of the color's channels, so grayscale images give their gray.
+    fn scalar(&self, uv: &Vec2, point: &Vec3, normal: &Vec3) -> f32 {
+        glm::comp_add(&self.value(uv, point, normal)) / 3.0
+    }
+}
+
+/// A constant color is a texture that is the same everywhere.
+impl Texture for Vec3 {
+    fn value(&self, _uv: &Vec2, _point: &Vec3, _normal: &Vec3) -> Vec3 {
+        *self
+    }
+}
+
+/// A constant number is a gray texture that is the same everywhere.
+impl Texture for f32 {
+    fn value(&self, _uv: &Vec2, _point: &Vec3, _normal: &Vec3) -> Vec3 {
+        vec3(*self, *self, *self)
+    }
+}
+
+/// How an image texture continues beyond the [0, 1] range of uvs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WrapMode {
+    /// Tiles the image.
+    Repeat,
+    /// Extends the pixels on the edge of the image outwards.
+    Clamp,
+}
+
+impl WrapMode {
+    // index of the pixel to use for pixel i in a row or column of n pixels
+    fn apply(self, i: i64, n: u32) -> usize {
+        let n = i64::from(n);
+        match self {
+            WrapMode::Repeat => i.rem_euclid(n) as usize,
+            WrapMode::Clamp => i.clamp(0, n - 1) as usize,
+        }
+    }
+}
+
+/// Image mapped onto a surface through its uvs, with (0, 0) the bottom left corner of the image
+/// and (1, 1) the top right. Pixels are filtered bilinearly.
+#[derive(Clone)]
+pub struct ImageTexture {
+    width: u32,
+    height: u32,
+    // row by row from the top. shared between copies of the texture, so objects using the same
+    // image do not each need their own copy of it.
+    pixels: Arc<RgbImage>,
+    wrap: WrapMode,
+    // linear value for each of the 256 levels of a channel
+    levels: [f32; 256],
+}
+
+impl ImageTexture {
+    /// Texture from an image with colors encoded with a gamma of 2.2, as images usually are.
+    /// The image repeats beyond its edges. Panics if the image is empty.
+    pub fn new(image: RgbImage) -> ImageTexture {
+        assert!(image.width() > 0 && image.height() > 0, "image is empty");
+        ImageTexture {
+            width: image.width(),
+            height: image.height(),
+            pixels: Arc::new(image),
+            wrap: WrapMode::Repeat,
+            levels: [0.0; 256],
+        }
+        .with_gamma(2.2)
+    }
+
+    /// Loads the image texture from a file, in any format the image crate can read.
+    pub fn load(path: &Path) -> ImageResult<ImageTexture> {
+        let image = image::open(path)?.to_rgb();
+        Ok(ImageTexture::new(image))
+    }
+
+    /// Sets how uvs outside of [0, 1] map onto the image.
+    pub fn with_wrap(self, wrap: WrapMode) -> Self {
+        ImageTexture { wrap, ..self }
+    }
+
+    /// Sets the gamma the image is encoded with. Images holding data rather than colors, like
+    /// normal maps, usually store it linearly, with a gamma of 1.
+    pub fn with_gamma(self, gamma: f32) -> Self {
+        let mut levels = [0.0; 256];
+        for (i, level) in levels.iter_mut().enumerate() {
+            *level = (i as f32 / 255.0).powf(gamma);
+        }
+        ImageTexture { levels, ..self }
+    }
+
+    fn pixel(&self, x: i64, y: i64) -> Vec3 {
+        let x = self.wrap.apply(x, self.width);
+        let y = self.wrap.apply(y, self.height);
+        let [r, g, b] = self.pixels.get_pixel(x as u32, y as u32).data;
+        vec3(
+            self.levels[r as usize],
+            self.levels[g as usize],
+            self.levels[b as usize],
+        )
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, uv: &Vec2, _point: &Vec3, _normal: &Vec3) -> Vec3 {
+        // pixel centers lie halfway between integer coordinates. v runs upwards while the rows
+        // of the image run downwards, so flip it.
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = (1.0 - uv.y) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = glm::lerp(&self.pixel(x0, y0), &self.pixel(x0 + 1, y0), fx);
+        let bottom = glm::lerp(&self.pixel(x0, y0 + 1), &self.pixel(x0 + 1, y0 + 1), fx);
+        glm::lerp(&top, &bottom, fy)
+    }
+}
+
+/// Checkerboard alternating between two textures, with squares of size `scale`. The squares
+/// are laid out over the uvs of a surface, or fill space as cubes for a solid checkerboard
+/// that does not depend on how the surface is parameterized.
+pub struct Checker {
+    even: Box<dyn Texture>,
+    odd: Box<dyn Texture>,
+    scale: f32,
+    solid: bool,
+}
+
+impl Checker {
+    /// Checkerboard over the uvs.
+    pub fn uv(even: Box<dyn Texture>, odd: Box<dyn Texture>, scale: f32) -> Checker {
+        Checker {
+            even,
+            odd,
+            scale,
+            solid: false,
+        }
+    }
+
+    /// Checkerboard of cubes filling space.
+    pub fn solid(even: Box<dyn Texture>, odd: Box<dyn Texture>, scale: f32) -> Checker {
+        Checker {
+            even,
+            odd,
+            scale,
+            solid: true,
+        }
+    }
+}
+
+impl Texture for Checker {
+    fn value(&self, uv: &Vec2, point: &Vec3, normal: &Vec3) -> Vec3 {
+        let cell = |x: f32| (x / self.scale).floor() as i64;
+        let sum = if self.solid {
+            cell(point.x) + cell(point.y) + cell(point.z)
+        } else {
+            cell(uv.x) + cell(uv.y)
+        };
+        if sum.rem_euclid(2) == 0 {
+            self.even.value(uv, point, normal)
+        } else {
+            self.odd.value(uv, point, normal)
+        }
+    }
+}
+
+/// Perlin's gradient noise (Perlin, 2002). It varies smoothly and randomly through space, with
+/// features about one unit apart.
+#[derive(Clone)]
+pub struct Perlin {
+    // random permutation of 0..256, repeated once so lookups need not wrap around
+    permutation: Vec<usize>,
+}
+
+impl Perlin {
+    /// Noise with a permutation shuffled by the seed, so different seeds give different noise.
+    pub fn new(seed: u64) -> Perlin {
+        let mut permutation: Vec<usize> = (0..256).collect();
+        permutation.shuffle(&mut StdRng::seed_from_u64(seed));
+        permutation.extend_from_within(..);
+        Perlin { permutation }
+    }
+
+    /// Noise at the point, between -1 and 1.
+    pub fn noise(&self, p: &Vec3) -> f32 {
+        let floor = glm::floor(p);
+        let f = p - floor;
+        let (x, y, z) = (
+            floor.x as i64 as usize & 255,
+            floor.y as i64 as usize & 255,
+            floor.z as i64 as usize & 255,
+        );
+        let (u, v, w) = (fade(f.x), fade(f.y), fade(f.z));
+
+        // hash each corner of the cell to one of the gradients, and blend their contributions
+        let perm = &self.permutation;
+        let a = perm[x] + y;
+        let (aa, ab) = (perm[a] + z, perm[a + 1] + z);
+        let b = perm[x + 1] + y;
+        let (ba, bb) = (perm[b] + z, perm[b + 1] + z);
+        let lerp = |t: f32, a: f32, b: f32| a + t * (b - a);
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(
+                    u,
+                    gradient(perm[aa], f.x, f.y, f.z),
+                    gradient(perm[ba], f.x - 1.0, f.y, f.z),
+                ),
+                lerp(
+                    u,
+                    gradient(perm[ab], f.x, f.y - 1.0, f.z),
+                    gradient(perm[bb], f.x - 1.0, f.y - 1.0, f.z),
+                ),
+            ),
+            lerp(
+                v,
+                lerp(
+                    u,
+                    gradient(perm[aa + 1], f.x, f.y, f.z - 1.0),
+                    gradient(perm[ba + 1], f.x - 1.0, f.y, f.z - 1.0),
+                ),
+                lerp(
+                    u,
+                    gradient(perm[ab + 1], f.x, f.y - 1.0, f.z - 1.0),
+                    gradient(perm[bb + 1], f.x - 1.0, f.y - 1.0, f.z - 1.0),
+                ),
+            ),
+        )
+    }
+
+    /// Fractal turbulence: the sum of the absolute value of `octaves` layers of noise, each at
+    /// twice the frequency and half the amplitude of the previous. Mostly between 0 and 1.
+    pub fn turbulence(&self, p: &Vec3, octaves: u32) -> f32 {
+        let mut sum = 0.0;
+        let mut p = *p;
+        let mut weight = 1.0;
+        for _ in 0..octaves {
+            sum += weight * self.noise(&p).abs();
+            weight *= 0.5;
+            p *= 2.0;
+        }
+        sum
+    }
+}
+
+// smooth interpolation weight, with zero first and second derivatives at 0 and 1
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+// dot product of the offset with one of the 12 gradients pointing to the edges of a cube,
+// picked by the hash
+fn gradient(hash: usize, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    let u = if h & 1 == 0 { u } else { -u };
+    let v = if h & 2 == 0 { v } else { -v };
+    u + v
+}
+
+/// Maps values between 0 and 1 to colors, blending linearly between the colors at a number of
+/// positions. Below the first and above the last position, the color stays the same.
+#[derive(Clone, Debug)]
+pub struct ColorRamp {
+    stops: Vec<(f32, Vec3)>,
+}
+
+impl ColorRamp {
+    /// Panics if there are no stops, or if they are not sorted by position.
+    pub fn new(stops: Vec<(f32, Vec3)>) -> ColorRamp {
+        assert!(!stops.is_empty(), "color ramp needs at least one color");
+        assert!(
+            stops.windows(2).all(|s| s[0].0 <= s[1].0),
+            "color ramp must be sorted by position"
+        );
+        ColorRamp { stops }
+    }
+
+    /// Ramp from black at 0 to white at 1.
+    pub fn grayscale() -> ColorRamp {
+        ColorRamp::new(vec![(0.0, vec3(0.0, 0.0, 0.0)), (1.0, vec3(1.0, 1.0, 1.0))])
+    }
+
+    /// Color at position `t`, blended between the stops around it. Positions before the first
+    /// or after the last stop get that stop's color.
+    pub fn color_at(&self, t: f32) -> Vec3 {
+        let stops = &self.stops;
+        let i = stops.partition_point(|s| s.0 <= t);
+        if i == 0 {
+            stops[0].1
+        } else if i == stops.len() {
+            stops[i - 1].1
+        } else {
+            let ((t0, c0), (t1, c1)) = (stops[i - 1], stops[i]);
+            glm::lerp(&c0, &c1, (t - t0) / (t1 - t0))
+        }
+    }
+}
+
+/// Pattern of a procedural texture, made from noise.
+#[derive(Clone, Copy, Debug)]
+pub enum NoisePattern {
+    /// Plain noise, like random soft blobs.
+    Noise,
+    /// Turbulence with the given number of octaves, like smoke or clouds.
+    Turbulence {
+        /// Number of layers of noise, each with features half the size of the one before.
+        octaves: u32,
+    },
+    /// Stripes across the x axis, with turbulence bending them by `distortion`.
+    Marble {
+        /// Octaves of the turbulence.
+        octaves: u32,
+        /// How far the turbulence bends the stripes.
+        distortion: f32,
+    },
+    /// Rings around the y axis, with turbulence bending them by `distortion`.
+    Wood {
+        /// Octaves of the turbulence.
+        octaves: u32,
+        /// How far the turbulence bends the rings.
+        distortion: f32,
+    },
+}
+
+/// Solid texture made from noise, that fills space without needing any uvs. The pattern is
+/// stretched to have features of about size `scale`, and its values between 0 and 1 get their
+/// color from a ramp.
+pub struct Procedural {
+    noise: Perlin,
+    pattern: NoisePattern,
+    scale: f32,
+    ramp: ColorRamp,
+}
+
+impl Procedural {
+    /// Procedural texture with features of size 1, with noise from seed 0.
+    pub fn new(pattern: NoisePattern, ramp: ColorRamp) -> Procedural {
+        Procedural {
+            noise: Perlin::new(0),
+            pattern,
+            scale: 1.0,
+            ramp,
+        }
+    }
+
+    /// Sets the size of the features of the pattern.
+    pub fn with_scale(self, scale: f32) -> Self {
+        Procedural { scale, ..self }
+    }
+
+    /// Sets the seed of the noise, for a different pattern of the same kind.
+    pub fn with_seed(self, seed: u64) -> Self {
+        Procedural {
+            noise: Perlin::new(seed),
+            ..self
+        }
+    }
+
+    // value of the pattern at the point, between 0 and 1
+    fn pattern_at(&self, point: &Vec3) -> f32 {
+        let p = point / self.scale;
+        match self.pattern {
+            NoisePattern::Noise => 0.5 * (1.0 + self.noise.noise(&p)),
+            NoisePattern::Turbulence { octaves } => self.noise.turbulence(&p, octaves),
+            NoisePattern::Marble {
+                octaves,
+                distortion,
+            } => {
+                let phase = 2.0 * PI * p.x + distortion * self.noise.turbulence(&p, octaves);
+                0.5 * (1.0 + phase.sin())
+            }
+            NoisePattern::Wood {
+                octaves,
+                distortion,
+            } => {
+                let radius = (p.x * p.x + p.z * p.z).sqrt();
+                let ring = radius + distortion * self.noise.turbulence(&p, octaves);
+                ring - ring.floor()
+            }
+        }
+    }
+}
+
+impl Texture for Procedural {
+    fn value(&self, _uv: &Vec2, point: &Vec3, _normal: &Vec3) -> Vec3 {
+        self.ramp.color_at(self.pattern_at(point).clamp(0.0, 1.0))
+    }
+}